@@ -0,0 +1,82 @@
+use clap::Parser;
+use road_intersection::ControllerKind;
+use std::path::PathBuf;
+
+// Command line of the simulator, every flag is optional
+#[derive(Debug, Parser)]
+#[command(
+    name = "road_intersection",
+    version,
+    about = "Road intersection traffic simulator"
+)]
+pub struct Cli {
+    /// Scenario file (TOML), the built-in scenario is used otherwise
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Seed of the simulation RNG, overrides the scenario one
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+
+    /// Run without a window, as fast as possible
+    #[arg(long, requires = "duration", conflicts_with = "window_size")]
+    pub headless: bool,
+
+    /// Stop after this many simulated seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_duration)]
+    pub duration: Option<f64>,
+
+    /// Directory where the run metrics are written
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// Window size, defaults to the scenario canvas size
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_window_size)]
+    pub window_size: Option<(u32, u32)>,
+
+    /// Signal controller used at startup, overrides the scenario one
+    #[arg(long, value_enum)]
+    pub controller: Option<ControllerKind>,
+}
+
+impl Cli {
+    // Checks clap cannot express, run before anything is started
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(output) = &self.output
+            && output.exists()
+            && !output.is_dir()
+        {
+            return Err(format!(
+                "output '{}' exists and is not a directory",
+                output.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_duration(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err("the duration must be a positive number of seconds".to_string());
+    }
+    Ok(seconds)
+}
+
+fn parse_window_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("'{}' is not of the form WIDTHxHEIGHT", value))?;
+    let width: u32 = width
+        .parse()
+        .map_err(|_| format!("invalid width '{}'", width))?;
+    let height: u32 = height
+        .parse()
+        .map_err(|_| format!("invalid height '{}'", height))?;
+    if width == 0 || height == 0 {
+        return Err("the window size must not be zero".to_string());
+    }
+    Ok((width, height))
+}
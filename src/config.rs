@@ -1,40 +1,19 @@
+use crate::simulation::{
+    ApproachMap, Footprint, Movement, VehicleSpawn, overlap_depth, sweep_junction,
+};
 use rand::Rng;
-
-// Grid constants for canva base drawings
-pub const CASE_SIZE: u32 = CANVA_WIDTH / 20;
-pub const GRID_WIDTH: u32 = CANVA_WIDTH / CASE_SIZE;
-pub const GRID_HEIGHT: u32 = CANVA_HEIGHT / CASE_SIZE;
-pub const GRID_WIDTH_PIXELS: u32 = GRID_WIDTH * CASE_SIZE;
-pub const GRID_HEIGHT_PIXELS: u32 = GRID_HEIGHT * CASE_SIZE;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
 
 // Constants for the display settings
-pub const CANVA_WIDTH: u32 = 1000;
-pub const CANVA_HEIGHT: u32 = 1000;
-pub const VEHICLE_WIDTH: u32 = CANVA_WIDTH / 20;
-pub const VEHICLE_HEIGHT: u32 = CANVA_HEIGHT / 20;
-// pub const TRAFFIC_LIGHT_WIDTH: u32 = CANVA_WIDTH / 20;
-// pub const TRAFFIC_LIGHT_HEIGHT: u32 = CANVA_HEIGHT / 20;
-pub const SAFE_DISTANCE: u32 = VEHICLE_WIDTH;
-pub const VEHICLE_SPEED: u16 = 200;
-
-// Starting and Ending positions of the Vehicles - South
-pub const BOTTOM_SPAWN: (f32, f32) = (((CANVA_WIDTH / 2) + (VEHICLE_WIDTH / 2)) as f32, 1100.0);
-pub const BOTTOM_DESTINATION: (f32, f32) =
-    (((CANVA_WIDTH / 2) + (VEHICLE_WIDTH / 2)) as f32, -100.0);
-
-// Starting and Ending positions of the Vehicles - North
-pub const UP_SPAWN: (f32, f32) = (((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2)) as f32, -100.0);
-pub const UP_DESTINATION: (f32, f32) = (((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2)) as f32, 1100.0);
-
-// Starting and Ending positions of the Vehicles - West
-pub const LEFT_SPAWN: (f32, f32) = (-100.0, ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2)) as f32);
-pub const LEFT_DESTINATION: (f32, f32) =
-    (1100.0, ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2)) as f32);
-
-// Starting and Ending positions of the Vehicles - East
-pub const RIGHT_SPAWN: (f32, f32) = (1100.0, ((CANVA_HEIGHT / 2) - (VEHICLE_HEIGHT / 2)) as f32);
-pub const RIGHT_DESTINATION: (f32, f32) =
-    (-100.0, ((CANVA_HEIGHT / 2) - (VEHICLE_HEIGHT / 2)) as f32);
+pub const RENDER_FPS: u32 = 60;
+/// Upper bound of simulation steps run for a single frame, to avoid a spiral of death
+pub const MAX_STEPS_PER_FRAME: u32 = 10;
+
+// Pedestrians wait on the sidewalk this far from the edge of the road, in pixels
+const CURB_OFFSET: f32 = 6.0;
 
 //Vector Director
 pub const BOTTOM_VECTOR: (f32, f32) = (0.0, 1.0);
@@ -42,32 +21,1066 @@ pub const TOP_VECTOR: (f32, f32) = (0.0, -1.0);
 pub const LEFT_VECTOR: (f32, f32) = (-1.0, 0.0);
 pub const RIGHT_VECTOR: (f32, f32) = (1.0, 0.0);
 
-//Traffic Lights Stop Position
-pub const NORTH_LIGHT: (f32, f32) = (
-    ((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2)) as f32,
-    ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2) - 2 * CASE_SIZE) as f32,
-);
-pub const SOUTH_LIGHT: (f32, f32) = (
-    ((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2) + CASE_SIZE) as f32,
-    ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2) + CASE_SIZE) as f32,
-);
-pub const EAST_LIGHT: (f32, f32) = (
-    ((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2) + 2 * CASE_SIZE) as f32,
-    ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2) - CASE_SIZE) as f32,
-);
-pub const WEST_LIGHT: (f32, f32) = (
-    ((CANVA_WIDTH / 2) - (VEHICLE_WIDTH / 2) - CASE_SIZE) as f32,
-    ((CANVA_HEIGHT / 2) + (VEHICLE_HEIGHT / 2)) as f32,
-);
-
-// Colors depending on the direction
-#[derive(Debug, Clone)]
+/// Scenario settings, loaded from a TOML file at startup
+/// Missing keys keep their built-in default value
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Size of the drawn area, in pixels, the roads cross at its center
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    /// Width of a single lane
+    pub lane_width: u32,
+    /// Lanes of the road in each direction
+    pub lanes: u32,
+    /// Turns allowed from every lane of each approach, from the median to the curb
+    /// Derived from the number of lanes when not set
+    pub lane_use: ApproachMap<Vec<LaneUse>>,
+    /// Size and performance of every vehicle class
+    pub classes: VehicleClasses,
+    /// Share of each class among the vehicles spawned on every approach
+    pub class_mix: ApproachMap<ClassMix>,
+    /// Relative weights of the turns taken by the vehicles of every approach, every turn
+    /// equally likely for the approaches not given
+    pub turning: ApproachMap<Option<TurningRatios>>,
+    /// Relative weights of the sides the vehicles of every approach leave through, replaces
+    /// `turning` for the approaches given
+    pub od_matrix: ApproachMap<Option<ApproachMap<f32>>>,
+    /// Vehicles arriving on their own on every approach, none by default
+    pub demand: ApproachMap<Demand>,
+    /// Factor applied to every demand rate over time, as (simulated seconds, factor) points
+    /// linearly interpolated and held past both ends, 1 when empty
+    pub demand_profile: Vec<(f32, f32)>,
+    /// Gap kept between two following vehicles at a standstill, in pixels
+    pub safe_distance: u32,
+    /// Car-following model, in pixels per second squared
+    pub comfortable_deceleration: f32,
+    /// Time gap a driver keeps to the vehicle ahead on top of `safe_distance`, in seconds
+    pub time_headway: f32,
+    /// Time a stopped driver takes to move off once the way ahead clears, in seconds
+    /// Adds up along a queue into its start-up lost time
+    pub startup_delay: f32,
+    /// Distance outside of the canvas where vehicles spawn and despawn
+    pub spawn_margin: u32,
+    /// Distance between the stop lines and the junction box, in pixels
+    /// Leaves room for the long vehicles swinging out of their lane in the turns
+    pub stop_line_setback: u32,
+    /// Width of the crosswalk across each leg, between the junction box and the stop line
+    pub crosswalk_width: u32,
+    /// Walking speed of the pedestrians, in pixels per second
+    pub pedestrian_speed: f32,
+    /// Pedestrians arriving on their own at each crosswalk, per hour, none by default
+    pub pedestrian_demand: f32,
+    /// Walk signal shown to the pedestrians for each call, in seconds
+    /// The flashing don't walk that follows lasts the time needed to cross
+    pub walk_time: f32,
+    /// Fixed simulation step in seconds, independent from the render frame rate
+    pub dt: f32,
+    /// Seed of the simulation RNG, a random one is drawn (and printed) when None
+    pub seed: Option<u64>,
+    /// Policy driving the traffic lights
+    pub controller: ControllerKind,
+    /// Green duration of each approach for the fixed-time controller, in seconds
+    pub green_time: f32,
+    /// Longest green given to an approach by the round-robin controller, in seconds
+    pub max_green_time: f32,
+    /// Cap the max-queue greens at `max_green_time` too, and give them to the longest waiting
+    /// of the phases with as many vehicles, they are held until the queue is empty otherwise
+    pub capped_max_queue: bool,
+    /// Amber shown between a green and the next red, in seconds
+    pub amber_time: f32,
+    /// Red and amber shown together before a green, in seconds
+    pub red_amber_time: f32,
+    /// Time every light stays red before the next green starts, to clear the junction
+    pub all_red_time: f32,
+    /// Deceleration a driver accepts to stop on amber, in pixels per second squared
+    pub braking_deceleration: f32,
+    /// Pairs of movements that cannot be green together, derived from the geometry when None
+    pub conflicts: Option<Vec<(Movement, Movement)>>,
+    /// How left turns are served by the controllers
+    pub left_turn: LeftTurnPhasing,
+    /// Smallest time gap in opposing traffic accepted by a permissive left turn, in seconds
+    pub left_turn_gap: f32,
+    /// Distance before its stop line, in pixels, from which an emergency vehicle preempts the
+    /// lights, until it is out of the junction
+    pub preemption_distance: f32,
+    /// Lane changing model: share of the gain or loss of the other drivers weighed in by a
+    /// driver
+    pub politeness: f32,
+    /// Advantage a driver needs to change lanes, in pixels per second squared
+    pub lane_change_threshold: f32,
+    /// Time taken to move across to the next lane, in seconds
+    pub lane_change_time: f32,
+    /// Time the vehicles of a crash stay stopped where they collided before they are removed, in
+    /// seconds, they keep driving when 0
+    pub crash_clearance_time: f32,
+    /// Panic on the first crash instead of recording it, for testing
+    pub strict_collisions: bool,
+    /// Length of the periods the traffic metrics are reported for, in seconds, they are only
+    /// reported for the whole run when 0
+    pub metrics_interval: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            canvas_width: 1000,
+            canvas_height: 1000,
+            lane_width: 50,
+            lanes: 1,
+            lane_use: ApproachMap::default(),
+            classes: VehicleClasses::default(),
+            class_mix: ApproachMap::default(),
+            turning: ApproachMap::default(),
+            od_matrix: ApproachMap::default(),
+            demand: ApproachMap::default(),
+            demand_profile: Vec::new(),
+            safe_distance: 50,
+            comfortable_deceleration: 250.0,
+            time_headway: 1.0,
+            startup_delay: 1.0,
+            spawn_margin: 100,
+            stop_line_setback: 35,
+            crosswalk_width: 30,
+            pedestrian_speed: 20.0,
+            pedestrian_demand: 0.0,
+            walk_time: 5.0,
+            dt: 1.0 / 60.0,
+            seed: None,
+            controller: ControllerKind::default(),
+            green_time: 5.0,
+            max_green_time: 10.0,
+            capped_max_queue: false,
+            amber_time: 2.0,
+            red_amber_time: 1.0,
+            all_red_time: 1.0,
+            braking_deceleration: 400.0,
+            conflicts: None,
+            left_turn: LeftTurnPhasing::default(),
+            left_turn_gap: 3.0,
+            preemption_distance: 400.0,
+            politeness: 0.3,
+            lane_change_threshold: 10.0,
+            lane_change_time: 1.5,
+            crash_clearance_time: 0.0,
+            strict_collisions: false,
+            metrics_interval: 300.0,
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate a scenario file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read scenario '{}': {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("invalid scenario '{}': {}", path.display(), e))?;
+        config
+            .validate()
+            .map_err(|e| format!("invalid scenario '{}': {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Check that the derived geometry makes sense
+    pub fn validate(&self) -> Result<(), String> {
+        if self.lane_width == 0 || self.lanes == 0 {
+            return Err("lane_width and lanes must be greater than 0".to_string());
+        }
+        let road = self.road_width() as u32;
+        if self.canvas_width < 4 * road || self.canvas_height < 4 * road {
+            return Err(format!(
+                "canvas ({}x{}) is too small for {} lanes of {}, it must be at least {} wide and \
+                 high",
+                self.canvas_width,
+                self.canvas_height,
+                self.lanes,
+                self.lane_width,
+                4 * road
+            ));
+        }
+        for spawn in VehicleSpawn::ALL {
+            self.validate_lane_use(spawn)
+                .map_err(|e| format!("lane_use of {}: {}", spawn.as_str().to_lowercase(), e))?;
+        }
+        for class in VehicleClass::ALL {
+            let spec = self.classes.get(class);
+            if spec.width == 0 || spec.length == 0 {
+                return Err(format!("{class} width and length must be greater than 0"));
+            }
+            if spec.width > self.lane_width {
+                return Err(format!(
+                    "{class} width ({}) does not fit in a lane of {}",
+                    spec.width, self.lane_width
+                ));
+            }
+            if spec.length > self.spawn_margin {
+                return Err(format!(
+                    "spawn_margin ({}) must be at least the {class} length ({}) so vehicles \
+                     spawn off screen",
+                    self.spawn_margin, spec.length
+                ));
+            }
+            let performance = [spec.max_speed, spec.max_acceleration];
+            if !performance
+                .iter()
+                .all(|&value| value > 0.0 && value.is_finite())
+            {
+                return Err(format!(
+                    "{class} max_speed and max_acceleration must be greater than 0"
+                ));
+            }
+        }
+        for (spawn, mix) in self.class_mix.iter() {
+            mix.validate()
+                .map_err(|e| format!("class_mix of {}: {}", spawn.as_str().to_lowercase(), e))?;
+        }
+        for spawn in VehicleSpawn::ALL {
+            self.validate_turning(spawn)
+                .map_err(|e| format!("turns of {}: {}", spawn.as_str().to_lowercase(), e))?;
+        }
+        for (spawn, demand) in self.demand.iter() {
+            demand
+                .validate()
+                .map_err(|e| format!("demand of {}: {}", spawn.as_str().to_lowercase(), e))?;
+        }
+        if !self
+            .demand_profile
+            .iter()
+            .all(|&(time, factor)| time.is_finite() && factor >= 0.0 && factor.is_finite())
+        {
+            return Err("demand_profile factors must not be negative".to_string());
+        }
+        if self
+            .demand_profile
+            .windows(2)
+            .any(|pair| pair[0].0 >= pair[1].0)
+        {
+            return Err("demand_profile times must be increasing".to_string());
+        }
+        if !(self.dt > 0.0 && self.dt <= 1.0) {
+            return Err(format!("dt ({}) must be in ]0, 1] seconds", self.dt));
+        }
+        if !(self.green_time > 0.0 && self.max_green_time > 0.0) {
+            return Err("green_time and max_green_time must be greater than 0".to_string());
+        }
+        let durations = [
+            self.amber_time,
+            self.red_amber_time,
+            self.all_red_time,
+            self.left_turn_gap,
+            self.time_headway,
+            self.startup_delay,
+            self.walk_time,
+            self.crash_clearance_time,
+            self.metrics_interval,
+        ];
+        if !durations
+            .iter()
+            .all(|&duration| duration >= 0.0 && duration.is_finite())
+        {
+            return Err(
+                "amber_time, red_amber_time, all_red_time, left_turn_gap, time_headway, \
+                 startup_delay, walk_time, crash_clearance_time and metrics_interval must not be \
+                 negative"
+                    .to_string(),
+            );
+        }
+        if !(self.braking_deceleration > 0.0 && self.braking_deceleration.is_finite()) {
+            return Err("braking_deceleration must be greater than 0".to_string());
+        }
+        if !(self.comfortable_deceleration > 0.0 && self.comfortable_deceleration.is_finite()) {
+            return Err("comfortable_deceleration must be greater than 0".to_string());
+        }
+        if self.crosswalk_width > self.stop_line_setback {
+            return Err(format!(
+                "stop_line_setback ({}) must be at least crosswalk_width ({}), vehicles stop \
+                 before the crosswalk",
+                self.stop_line_setback, self.crosswalk_width
+            ));
+        }
+        if !(self.pedestrian_speed > 0.0 && self.pedestrian_speed.is_finite()) {
+            return Err("pedestrian_speed must be greater than 0".to_string());
+        }
+        if !(self.pedestrian_demand >= 0.0 && self.pedestrian_demand.is_finite()) {
+            return Err("pedestrian_demand must not be negative".to_string());
+        }
+        if !(self.preemption_distance >= 0.0 && self.preemption_distance.is_finite()) {
+            return Err("preemption_distance must not be negative".to_string());
+        }
+        let lane_changing = [
+            self.politeness,
+            self.lane_change_threshold,
+            self.lane_change_time,
+        ];
+        if !lane_changing
+            .iter()
+            .all(|&value| value >= 0.0 && value.is_finite())
+        {
+            return Err(
+                "politeness, lane_change_threshold and lane_change_time must not be negative"
+                    .to_string(),
+            );
+        }
+        // The lane use keeps the movements of the same approach apart, they never cross
+        if let Some(pairs) = &self.conflicts
+            && let Some((a, b)) = pairs.iter().find(|(a, b)| a.from == b.from)
+        {
+            return Err(format!(
+                "conflict between {} and {} is not allowed, they come from the same approach",
+                a, b
+            ));
+        }
+        let setback = self.required_setback();
+        if self.stop_line_setback < setback {
+            return Err(format!(
+                "stop_line_setback ({}) must be at least {} so the turning vehicles stay clear \
+                 of the vehicles waiting at the other stop lines",
+                self.stop_line_setback, setback
+            ));
+        }
+        Ok(())
+    }
+
+    // Lanes given for every lane of the road, each turn allowed from one of them at least, and
+    // no turn from a lane crossing the path of a turn from a lane on its right
+    fn validate_lane_use(&self, spawn: VehicleSpawn) -> Result<(), String> {
+        let given = &self.lane_use[spawn];
+        if !given.is_empty() && given.len() != self.lanes as usize {
+            return Err(format!(
+                "{} lanes given for a road of {}",
+                given.len(),
+                self.lanes
+            ));
+        }
+        let uses = self.lane_uses(spawn);
+        if let Some(turn) = Direction::ALL
+            .into_iter()
+            .find(|&turn| !uses.iter().any(|lane| lane.allows(turn)))
+        {
+            return Err(format!("no lane allows turning {}", turn.as_str()));
+        }
+        if let Some(pair) = uses.windows(2).find(|pair| {
+            let rightmost = pair[0].turns().map(Direction::index).max();
+            let leftmost = pair[1].turns().map(Direction::index).min();
+            rightmost > leftmost
+        }) {
+            return Err(format!(
+                "a {} lane cannot be left of a {} lane, their turns cross",
+                pair[0], pair[1]
+            ));
+        }
+        Ok(())
+    }
+
+    // Given either as turning ratios or as a row of the OD matrix, without U-turns
+    fn validate_turning(&self, spawn: VehicleSpawn) -> Result<(), String> {
+        if let Some(row) = &self.od_matrix[spawn] {
+            if self.turning[spawn].is_some() {
+                return Err("given both in turning and in od_matrix".to_string());
+            }
+            if row[spawn] != 0.0 {
+                return Err(
+                    "U-turns are not allowed, the weight of its own side must be 0".to_string(),
+                );
+            }
+        }
+        self.turning_ratios(spawn).validate()
+    }
+
+    /// Weights of the turns taken on the approach, from its row of `od_matrix` when given
+    pub fn turning_ratios(&self, spawn: VehicleSpawn) -> TurningRatios {
+        let Some(row) = &self.od_matrix[spawn] else {
+            return self.turning[spawn].unwrap_or_default();
+        };
+        let weight = |turn| row[Movement::new(spawn, turn).exit()];
+        TurningRatios {
+            left: weight(Direction::Left),
+            up: weight(Direction::Up),
+            right: weight(Direction::Right),
+        }
+    }
+
+    /// Turns allowed from every lane of the approach, from the median to the curb
+    /// Not set, the median lane is for the left turns and the curb lane for the straight and
+    /// right ones, the lanes in between go straight, a single lane is shared by every turn
+    pub fn lane_uses(&self, spawn: VehicleSpawn) -> Vec<LaneUse> {
+        let given = &self.lane_use[spawn];
+        if !given.is_empty() {
+            return given.clone();
+        }
+        let lanes = self.lanes as usize;
+        match lanes {
+            1 => vec![LaneUse::Shared],
+            _ => (0..lanes)
+                .map(|lane| match lane {
+                    0 => LaneUse::Left,
+                    lane if lane + 1 == lanes => LaneUse::StraightRight,
+                    _ => LaneUse::Straight,
+                })
+                .collect(),
+        }
+    }
+
+    /// Factor of the demand rates at the given simulated time, from `demand_profile`
+    pub fn demand_factor(&self, time: f64) -> f32 {
+        let time = time as f32;
+        let profile = &self.demand_profile;
+        let Some(&(first_time, first)) = profile.first() else {
+            return 1.0;
+        };
+        if time <= first_time {
+            return first;
+        }
+        for pair in profile.windows(2) {
+            let ((from, a), (to, b)) = (pair[0], pair[1]);
+            if time < to {
+                return a + (b - a) * (time - from) / (to - from);
+            }
+        }
+        profile[profile.len() - 1].1
+    }
+
+    /// Smallest stop line setback keeping every class of `class_mix` out of the waiting areas
+    /// of the other approaches while it crosses the junction
+    /// Emergency vehicles can be sent on any approach, they are always counted
+    pub fn required_setback(&self) -> u32 {
+        let sweeps: Vec<(VehicleSpawn, Vec<Footprint>)> = self
+            .class_mix
+            .iter()
+            .flat_map(|(spawn, mix)| {
+                mix.classes()
+                    .chain(VehicleClass::EMERGENCY)
+                    .map(move |class| (spawn, class))
+            })
+            .flat_map(|(spawn, class)| {
+                let uses = self.lane_uses(spawn);
+                Direction::ALL.into_iter().flat_map(move |turn| {
+                    let movement = Movement::new(spawn, turn);
+                    let lanes: Vec<usize> = (0..uses.len())
+                        .filter(|&lane| uses[lane].allows(turn))
+                        .collect();
+                    lanes
+                        .into_iter()
+                        .map(move |lane| (spawn, sweep_junction(movement, lane, class, self, 1.0)))
+                })
+            })
+            .collect();
+        let is_clear = |setback: u32| {
+            sweeps.iter().all(|(from, footprints)| {
+                VehicleSpawn::ALL
+                    .iter()
+                    .filter(|&other| other != from)
+                    .all(|&other| {
+                        let area = self.waiting_area(other, setback);
+                        footprints
+                            .iter()
+                            .all(|footprint| overlap_depth(&area, footprint) <= 1.0)
+                    })
+            })
+        };
+        (0..self.spawn_margin)
+            .find(|&setback| is_clear(setback))
+            .unwrap_or(self.spawn_margin)
+    }
+
+    /// Grid constants for canva base drawings
+    pub fn case_size(&self) -> u32 {
+        self.lane_width
+    }
+
+    pub fn grid_width_pixels(&self) -> u32 {
+        self.canvas_width / self.case_size() * self.case_size()
+    }
+
+    pub fn grid_height_pixels(&self) -> u32 {
+        self.canvas_height / self.case_size() * self.case_size()
+    }
+
+    /// Width of the road in each direction, from the center line to the curb
+    pub fn road_width(&self) -> f32 {
+        (self.lanes * self.lane_width) as f32
+    }
+
+    // Distance between the center line and the middle of a lane, lanes are counted from the
+    // median
+    fn lane_offset(&self, lane: usize) -> f32 {
+        (lane as f32 + 0.5) * self.lane_width as f32
+    }
+
+    fn center(&self) -> (f32, f32) {
+        (
+            (self.canvas_width / 2) as f32,
+            (self.canvas_height / 2) as f32,
+        )
+    }
+
+    /// Starting positions of the Vehicles, in the given lane
+    pub fn spawn_position(&self, spawn: VehicleSpawn, lane: usize) -> (f32, f32) {
+        let (cx, cy) = self.center();
+        let lane = self.lane_offset(lane);
+        let margin = self.spawn_margin as f32;
+        match spawn {
+            VehicleSpawn::North => (cx - lane, -margin),
+            VehicleSpawn::South => (cx + lane, self.canvas_height as f32 + margin),
+            VehicleSpawn::East => (self.canvas_width as f32 + margin, cy - lane),
+            VehicleSpawn::West => (-margin, cy + lane),
+        }
+    }
+
+    /// Ending positions of the Vehicles, by the side and the lane they leave the intersection
+    /// through
+    pub fn destination(&self, exit: VehicleSpawn, lane: usize) -> (f32, f32) {
+        let (cx, cy) = self.center();
+        let lane = self.lane_offset(lane);
+        let margin = self.spawn_margin as f32;
+        match exit {
+            VehicleSpawn::North => (cx + lane, -margin),
+            VehicleSpawn::South => (cx - lane, self.canvas_height as f32 + margin),
+            VehicleSpawn::East => (self.canvas_width as f32 + margin, cy + lane),
+            VehicleSpawn::West => (-margin, cy - lane),
+        }
+    }
+
+    /// Point where the given approach lane enters the junction box
+    pub fn box_edge(&self, spawn: VehicleSpawn, lane: usize) -> (f32, f32) {
+        let (cx, cy) = self.center();
+        let lane = self.lane_offset(lane);
+        let edge = self.road_width();
+        match spawn {
+            VehicleSpawn::North => (cx - lane, cy - edge),
+            VehicleSpawn::South => (cx + lane, cy + edge),
+            VehicleSpawn::East => (cx + edge, cy - lane),
+            VehicleSpawn::West => (cx - edge, cy + lane),
+        }
+    }
+
+    /// Traffic Lights Stop Position - center of a vehicle of the given length touching the stop line
+    pub fn light_position(&self, spawn: VehicleSpawn, lane: usize, length: u32) -> (f32, f32) {
+        let edge = self.box_edge(spawn, lane);
+        let heading = spawn.vector();
+        let back = length as f32 / 2.0 + self.stop_line_setback as f32;
+        (edge.0 - heading.0 * back, edge.1 - heading.1 * back)
+    }
+
+    // Approach lanes behind a stop line `setback` pixels away from the box, up to the spawn
+    fn waiting_area(&self, spawn: VehicleSpawn, setback: u32) -> Footprint {
+        // Along the boundary between the median and the curb lanes, widened across the heading
+        // to every lane
+        let lanes = self.lanes as usize;
+        let (median, curb) = (self.box_edge(spawn, 0), self.box_edge(spawn, lanes - 1));
+        let edge = ((median.0 + curb.0) / 2.0, (median.1 + curb.1) / 2.0);
+        let (hx, hy) = spawn.vector();
+        let near = (edge.0 - hx * setback as f32, edge.1 - hy * setback as f32);
+        let (median, curb) = (
+            self.spawn_position(spawn, 0),
+            self.spawn_position(spawn, lanes - 1),
+        );
+        let far = ((median.0 + curb.0) / 2.0, (median.1 + curb.1) / 2.0);
+        let half = self.road_width() / 2.0;
+        let (min_x, max_x) = (
+            near.0.min(far.0) - half * hy.abs(),
+            near.0.max(far.0) + half * hy.abs(),
+        );
+        let (min_y, max_y) = (
+            near.1.min(far.1) - half * hx.abs(),
+            near.1.max(far.1) + half * hx.abs(),
+        );
+        [
+            (min_x, min_y),
+            (max_x, min_y),
+            (max_x, max_y),
+            (min_x, max_y),
+        ]
+    }
+
+    /// Distance past the box edge of the corner where a vehicle of the given lane turns, None
+    /// when going straight
+    /// The approach lane and the exit lane of the same rank cross there, the turn is a quarter
+    /// circle around it
+    pub fn turn_offset(&self, direction: Direction, lane: usize) -> Option<f32> {
+        let (road, lane) = (self.road_width(), self.lane_offset(lane));
+        match direction {
+            Direction::Right => Some(road - lane),
+            Direction::Left => Some(road + lane),
+            Direction::Up => None,
+        }
+    }
+
+    /// Both ends of the middle line of the crosswalk across the given leg, on the curbs where
+    /// the pedestrians wait
+    pub fn crosswalk_ends(&self, leg: VehicleSpawn) -> ((f32, f32), (f32, f32)) {
+        let (cx, cy) = self.center();
+        let (hx, hy) = leg.vector();
+        let back = self.road_width() + self.crosswalk_width as f32 / 2.0;
+        let middle = (cx - hx * back, cy - hy * back);
+        let half = self.crossing_length() / 2.0;
+        (
+            (middle.0 + hy * half, middle.1 - hx * half),
+            (middle.0 - hy * half, middle.1 + hx * half),
+        )
+    }
+
+    /// Corners of the crosswalk across the given leg, over the whole width of the road
+    pub fn crosswalk_area(&self, leg: VehicleSpawn) -> Footprint {
+        let (cx, cy) = self.center();
+        let (hx, hy) = leg.vector();
+        let road = self.road_width();
+        let (near, far) = (road, road + self.crosswalk_width as f32);
+        let corner =
+            |back: f32, across: f32| (cx - hx * back - hy * across, cy - hy * back + hx * across);
+        [
+            corner(near, -road),
+            corner(near, road),
+            corner(far, road),
+            corner(far, -road),
+        ]
+    }
+
+    /// Distance walked from curb to curb, across both directions of the road
+    pub fn crossing_length(&self) -> f32 {
+        2.0 * (self.road_width() + CURB_OFFSET)
+    }
+
+    /// Time a pedestrian takes to cross, the flashing don't walk lasts that long
+    pub fn crossing_time(&self) -> f32 {
+        self.crossing_length() / self.pedestrian_speed
+    }
+
+    /// Area (min x, min y, max x, max y) where the two roads cross
+    pub fn junction_box(&self) -> (f32, f32, f32, f32) {
+        let (cx, cy) = self.center();
+        let half = self.road_width();
+        (cx - half, cy - half, cx + half, cy + half)
+    }
+
+    /// Box (min x, min y, max x, max y) the center of a vehicle of the given length must leave
+    /// to be out of the junction
+    pub fn junction_bounds(&self, length: u32) -> (f32, f32, f32, f32) {
+        let (cx, cy) = self.center();
+        let half = self.road_width() + length as f32 / 2.0;
+        (cx - half, cy - half, cx + half, cy + half)
+    }
+}
+
+/// Available traffic light policies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControllerKind {
+    /// Green to the longest queue, held until it is empty, or `max_green_time` when capped
+    #[default]
+    MaxQueue,
+    /// Approaches served in turn, each until empty or `max_green_time`
+    RoundRobin,
+    /// Approaches served in turn for `green_time` each, whatever the demand
+    FixedTime,
+}
+
+/// Kinds of vehicles, each with its own size, performance and look
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VehicleClass {
+    Car,
+    Truck,
+    Bus,
+    Motorcycle,
+    /// Emergency vehicles, the others yield to them and the lights are preempted for them
+    Ambulance,
+    FireTruck,
+}
+
+impl VehicleClass {
+    pub const ALL: [VehicleClass; 6] = [
+        VehicleClass::Car,
+        VehicleClass::Truck,
+        VehicleClass::Bus,
+        VehicleClass::Motorcycle,
+        VehicleClass::Ambulance,
+        VehicleClass::FireTruck,
+    ];
+
+    pub const EMERGENCY: [VehicleClass; 2] = [VehicleClass::Ambulance, VehicleClass::FireTruck];
+
+    pub fn is_emergency(&self) -> bool {
+        Self::EMERGENCY.contains(self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            VehicleClass::Car => "car",
+            VehicleClass::Truck => "truck",
+            VehicleClass::Bus => "bus",
+            VehicleClass::Motorcycle => "motorcycle",
+            VehicleClass::Ambulance => "ambulance",
+            VehicleClass::FireTruck => "fire-truck",
+        }
+    }
+}
+
+impl std::fmt::Display for VehicleClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Size and performance of a vehicle class, sizes in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassSpec {
+    /// Across the heading, narrower than the lane so that it stays in it through a turn
+    pub width: u32,
+    /// Along the heading
+    pub length: u32,
+    /// Speed wanted on a free road, in pixels per second
+    pub max_speed: f32,
+    /// In pixels per second squared
+    pub max_acceleration: f32,
+}
+
+/// Spec of every class, a class given in a scenario must be given in full
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VehicleClasses {
+    pub car: ClassSpec,
+    pub truck: ClassSpec,
+    pub bus: ClassSpec,
+    pub motorcycle: ClassSpec,
+    pub ambulance: ClassSpec,
+    #[serde(rename = "fire-truck")]
+    pub fire_truck: ClassSpec,
+}
+
+impl Default for VehicleClasses {
+    fn default() -> Self {
+        Self {
+            car: ClassSpec {
+                width: 30,
+                length: 50,
+                max_speed: 200.0,
+                max_acceleration: 150.0,
+            },
+            truck: ClassSpec {
+                width: 40,
+                length: 90,
+                max_speed: 150.0,
+                max_acceleration: 60.0,
+            },
+            bus: ClassSpec {
+                width: 40,
+                length: 100,
+                max_speed: 160.0,
+                max_acceleration: 80.0,
+            },
+            motorcycle: ClassSpec {
+                width: 14,
+                length: 25,
+                max_speed: 220.0,
+                max_acceleration: 250.0,
+            },
+            ambulance: ClassSpec {
+                width: 32,
+                length: 60,
+                max_speed: 240.0,
+                max_acceleration: 180.0,
+            },
+            fire_truck: ClassSpec {
+                width: 40,
+                length: 80,
+                max_speed: 200.0,
+                max_acceleration: 100.0,
+            },
+        }
+    }
+}
+
+impl VehicleClasses {
+    pub fn get(&self, class: VehicleClass) -> &ClassSpec {
+        match class {
+            VehicleClass::Car => &self.car,
+            VehicleClass::Truck => &self.truck,
+            VehicleClass::Bus => &self.bus,
+            VehicleClass::Motorcycle => &self.motorcycle,
+            VehicleClass::Ambulance => &self.ambulance,
+            VehicleClass::FireTruck => &self.fire_truck,
+        }
+    }
+}
+
+/// Relative weights of the classes spawned on an approach, only cars by default
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct ClassMix(pub BTreeMap<VehicleClass, f32>);
+
+impl Default for ClassMix {
+    fn default() -> Self {
+        Self(BTreeMap::from([(VehicleClass::Car, 1.0)]))
+    }
+}
+
+impl ClassMix {
+    fn validate(&self) -> Result<(), String> {
+        if !self
+            .0
+            .values()
+            .all(|&weight| weight >= 0.0 && weight.is_finite())
+        {
+            return Err("weights must not be negative".to_string());
+        }
+        if self.0.values().sum::<f32>() <= 0.0 {
+            return Err("at least one class needs a weight greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// Classes with a chance to be spawned
+    pub fn classes(&self) -> impl Iterator<Item = VehicleClass> + '_ {
+        self.0
+            .iter()
+            .filter(|(_, weight)| **weight > 0.0)
+            .map(|(class, _)| *class)
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> VehicleClass {
+        let total: f32 = self.0.values().sum();
+        let mut draw = rng.random_range(0.0..total);
+        for class in self.classes() {
+            draw -= self.0[&class];
+            if draw < 0.0 {
+                return class;
+            }
+        }
+        // Rounding left the draw on the upper bound, the last class takes it
+        self.classes().last().unwrap_or(VehicleClass::Car)
+    }
+}
+
+/// Relative weights of the turns taken on an approach, every turn equally likely by default
+/// Written e.g. `north = { left = 0.2, straight = 0.7, right = 0.1 }`, a turn left out is
+/// never taken
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurningRatios {
+    #[serde(default)]
+    pub left: f32,
+    #[serde(default, alias = "straight")]
+    pub up: f32,
+    #[serde(default)]
+    pub right: f32,
+}
+
+impl Default for TurningRatios {
+    fn default() -> Self {
+        Self {
+            left: 1.0,
+            up: 1.0,
+            right: 1.0,
+        }
+    }
+}
+
+impl TurningRatios {
+    fn validate(&self) -> Result<(), String> {
+        let weights = Direction::ALL.map(|turn| self.weight(turn));
+        if !weights
+            .iter()
+            .all(|&weight| weight >= 0.0 && weight.is_finite())
+        {
+            return Err("weights must not be negative".to_string());
+        }
+        if weights.iter().sum::<f32>() <= 0.0 {
+            return Err("at least one turn needs a weight greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn weight(&self, turn: Direction) -> f32 {
+        match turn {
+            Direction::Left => self.left,
+            Direction::Up => self.up,
+            Direction::Right => self.right,
+        }
+    }
+
+    /// Share of the vehicles taking the given turn, between 0 and 1
+    pub fn share(&self, turn: Direction) -> f32 {
+        self.weight(turn) / (self.left + self.up + self.right)
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Direction {
+        let mut draw = rng.random_range(0.0..self.left + self.up + self.right);
+        let mut turns = Direction::ALL
+            .into_iter()
+            .filter(|&turn| self.weight(turn) > 0.0);
+        let mut last = Direction::Up;
+        for turn in turns.by_ref() {
+            draw -= self.weight(turn);
+            last = turn;
+            if draw < 0.0 {
+                return turn;
+            }
+        }
+        // Rounding left the draw on the upper bound, the last turn takes it
+        last
+    }
+}
+
+/// Random process the arrivals of an approach follow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArrivalProcess {
+    /// Independent arrivals, exponential headways
+    #[default]
+    Poisson,
+    /// Exponential headways never shorter than `min_headway`, for platoons behind a slow driver
+    ShiftedExponential,
+    /// Evenly spaced arrivals
+    Constant,
+}
+
+/// Automatic arrivals on an approach, written e.g. `north = { rate = 600.0 }`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Demand {
+    /// Vehicles per hour, before the factor of `demand_profile`
+    pub rate: f32,
+    pub arrivals: ArrivalProcess,
+    /// Shortest headway of the shifted exponential arrivals, in seconds
+    pub min_headway: f32,
+}
+
+impl Default for Demand {
+    fn default() -> Self {
+        Self {
+            rate: 0.0,
+            arrivals: ArrivalProcess::default(),
+            min_headway: 1.0,
+        }
+    }
+}
+
+impl Demand {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.rate >= 0.0 && self.rate.is_finite()) {
+            return Err("rate must not be negative".to_string());
+        }
+        if !(self.min_headway >= 0.0 && self.min_headway.is_finite()) {
+            return Err("min_headway must not be negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// Time to the next arrival at `rate` vehicles per hour, in seconds
+    /// A shifted exponential headway keeps the mean one of the rate, down to `min_headway`
+    pub fn headway(&self, rate: f32, rng: &mut impl Rng) -> f64 {
+        let mean = 3600.0 / rate as f64;
+        match self.arrivals {
+            ArrivalProcess::Poisson => mean * unit_exponential(rng),
+            ArrivalProcess::ShiftedExponential => {
+                let shift = (self.min_headway as f64).min(mean);
+                shift + (mean - shift) * unit_exponential(rng)
+            }
+            ArrivalProcess::Constant => mean,
+        }
+    }
+}
+
+// Exponential draw of mean 1, by inverse transform
+fn unit_exponential(rng: &mut impl Rng) -> f64 {
+    -(1.0 - rng.random::<f64>()).ln()
+}
+
+/// Phase plans available for the left turns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LeftTurnPhasing {
+    /// Every approach served alone, left turns included
+    #[default]
+    Protected,
+    /// Opposing approaches served together, left turns yield to the opposing traffic
+    Permissive,
+    /// Left arrow of each approach first, then both approaches with permissive left turns
+    Leading,
+    /// Both approaches with permissive left turns first, then the left arrow of each approach
+    /// The max-queue controller picks the arrows by demand, this order only breaking its ties
+    Lagging,
+}
+
+/// Turns allowed from a lane, written e.g. "left" or "straight-right" in scenario files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LaneUse {
+    Left,
+    Straight,
+    Right,
+    LeftStraight,
+    StraightRight,
+    /// Every turn
+    Shared,
+}
+
+impl LaneUse {
+    pub fn allows(self, turn: Direction) -> bool {
+        match self {
+            LaneUse::Left => turn == Direction::Left,
+            LaneUse::Straight => turn == Direction::Up,
+            LaneUse::Right => turn == Direction::Right,
+            LaneUse::LeftStraight => turn != Direction::Right,
+            LaneUse::StraightRight => turn != Direction::Left,
+            LaneUse::Shared => true,
+        }
+    }
+
+    pub fn turns(self) -> impl Iterator<Item = Direction> {
+        Direction::ALL
+            .into_iter()
+            .filter(move |&turn| self.allows(turn))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            LaneUse::Left => "left",
+            LaneUse::Straight => "straight",
+            LaneUse::Right => "right",
+            LaneUse::LeftStraight => "left-straight",
+            LaneUse::StraightRight => "straight-right",
+            LaneUse::Shared => "shared",
+        }
+    }
+}
+
+impl std::fmt::Display for LaneUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Colors depending on the direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     Left,
     Up,
     Right,
 }
 impl Direction {
+    /// Every turn, in the order used to store per-turn values
+    pub const ALL: [Direction; 3] = [Direction::Left, Direction::Up, Direction::Right];
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Direction::Left => 0,
+            Direction::Up => 1,
+            Direction::Right => 2,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Direction::Left => "left",
+            Direction::Up => "up",
+            Direction::Right => "right",
+        }
+    }
+
     pub fn color(&self) -> (u8, u8, u8) {
         match self {
             Direction::Left => (220, 220, 30),
@@ -75,13 +1088,86 @@ impl Direction {
             Direction::Right => (220, 30, 220),
         }
     }
+}
 
-    pub fn random() -> Self {
-        let mut rng = rand::rng();
-        match rng.random_range(0..3) {
-            1 => Direction::Right,
-            2 => Direction::Left,
-            _ => Direction::Up,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(scenario: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(scenario).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn turn_left_out_of_a_turning_table_is_never_taken() {
+        let config = parse("[turning]\nnorth = { left = 0.2, straight = 0.8 }").unwrap();
+        let ratios = config.turning_ratios(VehicleSpawn::North);
+        assert_eq!(
+            ratios,
+            TurningRatios {
+                left: 0.2,
+                up: 0.8,
+                right: 0.0
+            }
+        );
+        assert_eq!(ratios.share(Direction::Right), 0.0);
+        assert_eq!(
+            config.turning_ratios(VehicleSpawn::South),
+            TurningRatios::default()
+        );
+    }
+
+    #[test]
+    fn turning_table_and_od_matrix_row_of_one_approach_are_rejected() {
+        let scenario = "[turning]\n\
+                        north = { left = 1.0, straight = 1.0, right = 1.0 }\n\
+                        [od_matrix]\n\
+                        north = { east = 1.0, south = 2.0, west = 1.0 }";
+        assert!(parse(scenario).is_err());
+        // Each form alone is accepted
+        assert!(parse("[turning]\nnorth = { left = 1.0, straight = 1.0, right = 1.0 }").is_ok());
+        assert!(parse("[od_matrix]\nnorth = { east = 1.0, south = 2.0, west = 1.0 }").is_ok());
+    }
+
+    #[test]
+    fn stop_line_setback_too_short_for_the_turning_vehicles_is_rejected() {
+        let required = Config::default().required_setback();
+        assert!(required <= Config::default().stop_line_setback);
+        let error = parse(&format!("stop_line_setback = {}", required - 1)).unwrap_err();
+        assert!(
+            error.contains(&format!("must be at least {required}")),
+            "{error}"
+        );
+        // Buses swing wider in the turns
+        let buses = "[class_mix]\nnorth = { bus = 1.0 }";
+        let required = parse(&format!("stop_line_setback = 100\n{buses}"))
+            .unwrap()
+            .required_setback();
+        assert!(required > Config::default().stop_line_setback);
+        assert!(parse(buses).is_err());
+        assert!(parse(&format!("stop_line_setback = {required}\n{buses}")).is_ok());
+    }
+
+    #[test]
+    fn lane_uses_whose_turns_cross_are_rejected() {
+        let error =
+            parse("lanes = 2\n[lane_use]\nnorth = [\"straight-right\", \"left\"]").unwrap_err();
+        assert!(error.contains("their turns cross"), "{error}");
+        let error =
+            parse("lanes = 2\n[lane_use]\nnorth = [\"right\", \"left-straight\"]").unwrap_err();
+        assert!(error.contains("their turns cross"), "{error}");
+        // Turns may be shared by neighbouring lanes, in order
+        assert!(
+            parse("lanes = 2\n[lane_use]\nnorth = [\"left-straight\", \"straight-right\"]").is_ok()
+        );
+        assert!(parse("lanes = 2\n[lane_use]\nnorth = [\"left\", \"shared\"]").is_ok());
+    }
+
+    #[test]
+    fn turning_table_without_any_weight_is_rejected() {
+        assert!(parse("[turning]\nnorth = { right = 0.0 }").is_err());
+        assert!(parse("[turning]\nnorth = { uturn = 1.0 }").is_err());
     }
 }
@@ -1,26 +1,61 @@
-use rand::prelude::*;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+
+use crate::config::{Direction, VehicleClass};
+use crate::simulation::{Movement, Simulation, VehicleSpawn};
+
+// Row of keys spawning a vehicle for each movement, in the order of `Movement::all`: left,
+// straight and right of North, South, East then West
+const MOVEMENT_KEYS: [Keycode; 12] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Num5,
+    Keycode::Num6,
+    Keycode::Num7,
+    Keycode::Num8,
+    Keycode::Num9,
+    Keycode::Num0,
+    Keycode::Minus,
+    Keycode::Equals,
+];
+
+// Turn asked for with the arrow keys: Shift for left, Ctrl for right, drawn from the turning
+// ratios without a modifier
+fn modifier_turn(keymod: Mod) -> Option<Direction> {
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        Some(Direction::Left)
+    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        Some(Direction::Right)
+    } else {
+        None
+    }
+}
 
-use crate::render::Renderer;
+// Movement spawned by a key of the number row
+fn key_movement(keycode: Keycode) -> Option<Movement> {
+    let index = MOVEMENT_KEYS.iter().position(|&key| key == keycode)?;
+    Movement::all().nth(index)
+}
 
-// Function that will listen to user's inputs
-pub fn input_listener(event: Event, renderer: &mut Renderer) -> Result<(), String> {
+/// Function that will listen to user's inputs
+pub fn input_listener(event: Event, simulation: &mut Simulation) -> Result<(), String> {
     // Input listening
     match event {
         Event::Quit { .. }
         | Event::KeyDown {
             keycode: Some(Keycode::Escape),
             ..
-        } => return Err("<---------- Program end ---------->".to_string()),
+        } => Err("<---------- Program end ---------->".to_string()),
 
-        // Listening for the DOWN keypress
+        // Listening for the DOWN keypress - Shift or Ctrl held for a left or right turn
         Event::KeyDown {
             keycode: Some(Keycode::Down),
+            keymod,
             ..
         } => {
-            // println!("Down arrow pressed");
-            renderer.lanes.spawn_vehicle("down");
+            simulation.spawn_vehicle(VehicleSpawn::South, modifier_turn(keymod));
 
             Ok(())
         }
@@ -28,10 +63,10 @@ pub fn input_listener(event: Event, renderer: &mut Renderer) -> Result<(), Strin
         // Listening for the UP keypress
         Event::KeyDown {
             keycode: Some(Keycode::Up),
+            keymod,
             ..
         } => {
-            // println!("Up arrow pressed");
-            renderer.lanes.spawn_vehicle("up");
+            simulation.spawn_vehicle(VehicleSpawn::North, modifier_turn(keymod));
 
             Ok(())
         }
@@ -39,10 +74,10 @@ pub fn input_listener(event: Event, renderer: &mut Renderer) -> Result<(), Strin
         // Listening for the LEFT keypress
         Event::KeyDown {
             keycode: Some(Keycode::Left),
+            keymod,
             ..
         } => {
-            // println!("Left arrow pressed");
-            renderer.lanes.spawn_vehicle("left");
+            simulation.spawn_vehicle(VehicleSpawn::West, modifier_turn(keymod));
 
             Ok(())
         }
@@ -50,10 +85,25 @@ pub fn input_listener(event: Event, renderer: &mut Renderer) -> Result<(), Strin
         // Listening for the RIGHT keypress
         Event::KeyDown {
             keycode: Some(Keycode::Right),
+            keymod,
             ..
         } => {
-            // println!("Right arrow pressed");
-            renderer.lanes.spawn_vehicle("right");
+            simulation.spawn_vehicle(VehicleSpawn::East, modifier_turn(keymod));
+
+            Ok(())
+        }
+
+        // Listening to the number row keypresses - Vehicle doing the movement of the key
+        Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } if MOVEMENT_KEYS.contains(&keycode) => {
+            if let Some(movement) = key_movement(keycode) {
+                match simulation.spawn_vehicle(movement.from, Some(movement.turn)) {
+                    Some(_) => println!("-> {} vehicle", movement),
+                    None => println!("-> {} vehicle waiting to enter", movement),
+                }
+            }
 
             Ok(())
         }
@@ -63,67 +113,97 @@ pub fn input_listener(event: Event, renderer: &mut Renderer) -> Result<(), Strin
             keycode: Some(Keycode::R),
             ..
         } => {
-            // Generating a random number to randominze the direction
-            let mut rng = rand::rng();
-            let rand_num = rng.random_range(0..4);
-
-            match rand_num {
-                0 => {
-                    println!("-> Random generated Left Vehicle");
-                    renderer.lanes.spawn_vehicle("left");
-                }
-                1 => {
-                    println!("-> Random generated Right Vehicle");
-                    renderer.lanes.spawn_vehicle("right");
-                }
-                2 => {
-                    println!("-> Random generated Up Vehicle");
-                    renderer.lanes.spawn_vehicle("up");
-                }
-                _ => {
-                    println!("-> Random generated Down Vehicle");
-                    renderer.lanes.spawn_vehicle("down");
-                }
+            // Drawing the side from the simulation RNG to keep runs reproducible
+            let spawn = simulation.spawn_random_vehicle();
+            println!("-> Random generated {} Vehicle", spawn.as_str());
+
+            Ok(())
+        }
+
+        // Listening to the A and F keypresses - Ambulance or fire truck from a random side,
+        // turning as asked by the modifiers like the arrow keys
+        Event::KeyDown {
+            keycode: Some(keycode @ (Keycode::A | Keycode::F)),
+            keymod,
+            ..
+        } => {
+            let class = match keycode {
+                Keycode::A => VehicleClass::Ambulance,
+                _ => VehicleClass::FireTruck,
+            };
+            let spawn = simulation.random_spawn();
+            match simulation.spawn_vehicle_of_class(spawn, class, modifier_turn(keymod)) {
+                Some(_) => println!("-> {} sent from {}", class, spawn.as_str()),
+                None => println!("-> {} waiting to enter from {}", class, spawn.as_str()),
             }
 
             Ok(())
         }
 
-        // DEBUG INPUT - TRIGGERING TRAFFIC LIGHTS MANUALLY
-
-        // Event::KeyDown {
-        //     keycode: Some(Keycode::N),
-        //     ..
-        // } => {
-        //     renderer.change_state("North");
-        //     Ok(())
-        // }
-        //
-        // Event::KeyDown {
-        //     keycode: Some(Keycode::W),
-        //     ..
-        // } => {
-        //     renderer.change_state("West");
-        //     Ok(())
-        // }
-        //
-        // Event::KeyDown {
-        //     keycode: Some(Keycode::S),
-        //     ..
-        // } => {
-        //     renderer.change_state("South");
-        //     Ok(())
-        // }
-        //
-        // Event::KeyDown {
-        //     keycode: Some(Keycode::E),
-        //     ..
-        // } => {
-        //     renderer.change_state("East");
-        //     Ok(())
-        // }
+        // Listening to the G keypress - Automatic arrivals of vehicles and pedestrians on or off
+        Event::KeyDown {
+            keycode: Some(Keycode::G),
+            ..
+        } => {
+            let enabled = simulation.demand.toggle();
+            println!(
+                "-> Demand generator {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+
+            Ok(())
+        }
+
+        // Listening to the P keypress - Pedestrian at a random crosswalk
+        Event::KeyDown {
+            keycode: Some(Keycode::P),
+            ..
+        } => {
+            let crosswalk = simulation.random_spawn();
+            simulation.spawn_pedestrian(crosswalk);
+            println!("-> Pedestrian at the {} crosswalk", crosswalk.as_str());
+
+            Ok(())
+        }
 
         // In case of no input from user, no error generated
         _ => Ok(()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Direction::{Left, Right, Up};
+    use VehicleSpawn::{North, South, West};
+
+    #[test]
+    fn modifiers_pick_the_turn_of_the_arrow_keys() {
+        assert_eq!(modifier_turn(Mod::LSHIFTMOD), Some(Left));
+        assert_eq!(modifier_turn(Mod::RSHIFTMOD), Some(Left));
+        assert_eq!(modifier_turn(Mod::LCTRLMOD), Some(Right));
+        assert_eq!(modifier_turn(Mod::RCTRLMOD), Some(Right));
+        assert_eq!(modifier_turn(Mod::NOMOD), None);
+        // Lock keys are not modifiers
+        assert_eq!(modifier_turn(Mod::NUMMOD | Mod::CAPSMOD), None);
+    }
+
+    #[test]
+    fn number_row_spawns_every_movement_once() {
+        let movements: Vec<Movement> = MOVEMENT_KEYS
+            .iter()
+            .map(|&key| key_movement(key).unwrap())
+            .collect();
+        assert_eq!(movements, Movement::all().collect::<Vec<_>>());
+        assert_eq!(
+            key_movement(Keycode::Num1),
+            Some(Movement::new(North, Left))
+        );
+        assert_eq!(key_movement(Keycode::Num5), Some(Movement::new(South, Up)));
+        assert_eq!(
+            key_movement(Keycode::Equals),
+            Some(Movement::new(West, Right))
+        );
+        assert_eq!(key_movement(Keycode::Q), None);
+    }
+}
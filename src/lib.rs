@@ -0,0 +1,34 @@
+//! Road intersection traffic simulator.
+//!
+//! The simulation core is independent from any display: build a [`Simulation`]
+//! from a [`Config`] scenario, spawn vehicles, [`Simulation::step`] it and query
+//! its state. The SDL view and keyboard bindings live behind the `gui` feature.
+//!
+//! ```
+//! use road_intersection::{Config, Simulation, VehicleSpawn};
+//!
+//! let mut simulation = Simulation::new(Config::default(), 42);
+//! simulation.spawn_vehicle(VehicleSpawn::North, None);
+//! for _ in 0..60 {
+//!     simulation.step();
+//! }
+//! assert_eq!(simulation.vehicle_count(), 1);
+//! ```
+
+pub mod config;
+#[cfg(feature = "gui")]
+pub mod input;
+#[cfg(feature = "gui")]
+pub mod render;
+pub mod simulation;
+
+pub use config::{
+    ArrivalProcess, ClassMix, Config, ControllerKind, Demand, Direction, LaneUse, LeftTurnPhasing,
+    TurningRatios, VehicleClass,
+};
+pub use simulation::{
+    ApproachMap, ApproachMetrics, CollisionDetector, ConflictMatrix, Crash, DemandGenerator,
+    Driver, EventKind, Metrics, MetricsReport, Movement, MovementMap, Pedestrian, PedestrianSignal,
+    Preemption, SignalHead, SignalState, Simulation, SimulationEvent, TrafficLanes, TrafficLight,
+    TripRecord, Vehicle, VehicleSpawn, VehicleState,
+};
@@ -1,39 +1,117 @@
-use std::time::Duration;
+use clap::Parser;
+use std::fs;
+use std::path::Path;
 
-mod config;
-mod input;
-mod render;
-mod simulation;
+mod cli;
 
-use crate::{
-    config::{CANVA_HEIGHT, CANVA_WIDTH},
-    render::Renderer,
+use crate::cli::Cli;
+use road_intersection::{
+    Config, Direction, EventKind, MetricsReport, Movement, Simulation, SimulationEvent, TripRecord,
+    VehicleSpawn,
 };
-use input::input_listener;
 
 pub fn main() {
+    let cli = Cli::parse();
+    if let Err(msg) = cli.validate() {
+        eprintln!("error: {}", msg);
+        std::process::exit(2);
+    }
+
     println!("<---- Road intersection start ---->");
 
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
+    // Scenario given as `--config <file>`, the built-in one otherwise
+    let mut config = match &cli.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(msg) => {
+                eprintln!("error: {}", msg);
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+    if let Some(controller) = cli.controller {
+        config.controller = controller;
+    }
+
+    // Seed given as `--seed <n>`, falling back to the scenario one, then to a random one
+    let seed = cli.seed.or(config.seed).unwrap_or_else(rand::random);
+
+    // Creating the simulation - Holds the whole intersection state
+    let mut simulation = Simulation::new(config, seed);
+    println!("RNG seed: {}", simulation.seed);
+
+    if cli.headless {
+        // `--headless` requires `--duration`, checked by clap
+        run_headless(&mut simulation, cli.duration.unwrap_or_default());
+    } else if let Err(msg) = run_window(&mut simulation, &cli) {
+        eprintln!("error: {}", msg);
+        std::process::exit(1);
+    }
+
+    print_turning_ratios(&simulation);
+    print_report("whole run", &simulation.metrics.summary(simulation.time()));
+
+    if let Some(output) = &cli.output
+        && let Err(msg) = write_summary(output, &simulation)
+    {
+        eprintln!("error: {}", msg);
+        std::process::exit(1);
+    }
+}
+
+// Step the simulation as fast as possible, without any display
+fn run_headless(simulation: &mut Simulation, duration: f64) {
+    let mut reported = 0;
+    while simulation.time() < duration {
+        simulation.step();
+        log_events(&simulation.drain_events());
+        log_reports(simulation, &mut reported);
+    }
+    println!(
+        "<---------- Simulated {:.1}s ---------->",
+        simulation.time()
+    );
+}
+
+// Interactive run in a SDL window, until closed or `--duration` is reached
+#[cfg(feature = "gui")]
+fn run_window(simulation: &mut Simulation, cli: &Cli) -> Result<(), String> {
+    use road_intersection::config::RENDER_FPS;
+    use road_intersection::input::input_listener;
+    use road_intersection::render::Renderer;
+    use std::time::{Duration, Instant};
+
+    let config = simulation.config.clone();
+    let (width, height) = cli
+        .window_size
+        .unwrap_or((config.canvas_width, config.canvas_height));
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
 
     let window = video_subsystem
-        .window("road_intersection", CANVA_WIDTH, CANVA_HEIGHT)
+        .window("road_intersection", width, height)
         .position_centered()
         .build()
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
     // Creating the new renderer - To print on the screen
-    let mut renderer = Renderer::new(window).unwrap();
+    let mut renderer = Renderer::new(window, config)?;
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = sdl_context.event_pump()?;
+
+    let frame_duration = Duration::from_secs(1) / RENDER_FPS;
+    let mut last_frame = Instant::now();
+    let mut reported = 0;
 
     'running: loop {
+        let frame_start = Instant::now();
         renderer.canvas.clear();
 
         // Input listener - Vehicle spawning
         for event in event_pump.poll_iter() {
-            match input_listener(event, &mut renderer) {
+            match input_listener(event, simulation) {
                 Ok(()) => {}
                 Err(msg) => {
                     println!("{}", msg);
@@ -42,16 +120,226 @@ pub fn main() {
             }
         }
 
+        // Advancing the simulation by every fixed step due since the last frame
+        simulation.advance(frame_start - last_frame);
+        log_events(&simulation.drain_events());
+        log_reports(simulation, &mut reported);
+        last_frame = frame_start;
+
+        if let Some(duration) = cli.duration
+            && simulation.time() >= duration
+        {
+            println!("<---------- Duration reached ---------->");
+            break 'running;
+        }
+
         // Drawing the road and traffic lights
-        renderer.draw().unwrap();
+        renderer.draw(simulation)?;
 
-        // Drawing the vehicles
-        renderer.draw_vehicles();
+        // Drawing the vehicles, the backlogs and the pedestrians
+        renderer.draw_vehicles(simulation);
+        renderer.draw_backlogs(simulation)?;
+        renderer.draw_pedestrians(simulation)?;
 
         // Render the drawn picture to the screen
         renderer.canvas.present();
 
-        // Time between each loops - Frame rate
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        // Time between each loops - Frame rate, minus the time spent on this frame
+        ::std::thread::sleep(frame_duration.saturating_sub(frame_start.elapsed()));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_window(_simulation: &mut Simulation, _cli: &Cli) -> Result<(), String> {
+    Err("built without the `gui` feature, only `--headless` runs are available".to_string())
+}
+
+// Print the preemptions of the lights for emergency vehicles and the crashes
+fn log_events(events: &[SimulationEvent]) {
+    for event in events {
+        match event.kind {
+            EventKind::PreemptionStarted { id, approach } => println!(
+                "[{:.1}s] Lights preempted for vehicle {} from {}",
+                event.time,
+                id,
+                approach.as_str()
+            ),
+            EventKind::PreemptionEnded {
+                id,
+                approach,
+                duration,
+                delay,
+            } => println!(
+                "[{:.1}s] Preemption for vehicle {} from {} over after {:.1}s, {:.1}s of delay to \
+                 the movements whose green was cut",
+                event.time,
+                id,
+                approach.as_str(),
+                duration,
+                delay
+            ),
+            EventKind::VehicleCrashed(crash) => println!(
+                "[{:.1}s] Vehicles {} ({}) and {} ({}) crashed at ({:.0}, {:.0})",
+                event.time,
+                crash.vehicles[0],
+                crash.movements[0],
+                crash.vehicles[1],
+                crash.movements[1],
+                crash.position.0,
+                crash.position.1
+            ),
+            _ => {}
+        }
+    }
+}
+
+// Print the metrics of the periods over since the last call, `reported` of them already were
+fn log_reports(simulation: &Simulation, reported: &mut usize) {
+    for report in &simulation.metrics.reports[*reported..] {
+        print_report("period", report);
+    }
+    *reported = simulation.metrics.reports.len();
+}
+
+// Traffic figures of every approach over the period of the report
+fn print_report(label: &str, report: &MetricsReport) {
+    println!(
+        "Metrics, {} {:.1}s - {:.1}s:",
+        label, report.start, report.end
+    );
+    for (spawn, metrics) in report.approaches.iter() {
+        println!(
+            "  {:<5} throughput {} ({:.0}/h), delay mean {:.1}s p95 {:.1}s over {} trips, max \
+             queue {}, max wait {:.1}s",
+            spawn.as_str(),
+            metrics.throughput,
+            report.hourly_throughput(spawn),
+            metrics.mean_delay,
+            metrics.p95_delay,
+            metrics.completed,
+            metrics.max_queue,
+            metrics.max_wait
+        );
+    }
+}
+
+// Share of each turn among the turns drawn on every approach, against the configured one
+// Turns are counted once the vehicles are out of the junction, as they really took them, and
+// the ones asked for from the keyboard are left out
+fn print_turning_ratios(simulation: &Simulation) {
+    println!("Turning ratios, realised (configured):");
+    let realised = simulation.realised_movements();
+    for spawn in VehicleSpawn::ALL {
+        let ratios = simulation.config.turning_ratios(spawn);
+        let total: u32 = Direction::ALL
+            .iter()
+            .map(|&turn| realised[Movement::new(spawn, turn)])
+            .sum();
+        let shares: Vec<String> = Direction::ALL
+            .iter()
+            .map(|&turn| {
+                let count = realised[Movement::new(spawn, turn)];
+                let share = if total > 0 {
+                    count as f32 / total as f32
+                } else {
+                    0.0
+                };
+                format!(
+                    "{} {:.0}% ({:.0}%)",
+                    turn.as_str(),
+                    share * 100.0,
+                    ratios.share(turn) * 100.0
+                )
+            })
+            .collect();
+        println!(
+            "  {:<5} {:>4} vehicles - {}",
+            spawn.as_str(),
+            total,
+            shares.join(", ")
+        );
+    }
+}
+
+// Write the run summary in the output directory
+fn write_summary(output: &Path, simulation: &Simulation) -> Result<(), String> {
+    fs::create_dir_all(output)
+        .map_err(|e| format!("cannot create '{}': {}", output.display(), e))?;
+    let path = output.join("summary.txt");
+    let summary = format!(
+        "seed = {}\nsimulated_seconds = {:.3}\nvehicles_spawned = {}\nvehicles_on_road = {}\n\
+         vehicles_in_backlog = {}\nmean_entry_delay = {:.3}\ncrashes = {}\n",
+        simulation.seed,
+        simulation.time(),
+        simulation.lanes.spawned_count(),
+        simulation.vehicle_count(),
+        VehicleSpawn::ALL
+            .iter()
+            .map(|&spawn| simulation.backlog_length(spawn))
+            .sum::<usize>(),
+        simulation.mean_entry_delay(),
+        simulation.crashes().len(),
+    );
+    fs::write(&path, summary).map_err(|e| format!("cannot write '{}': {}", path.display(), e))?;
+
+    // Every period, then the whole run
+    let mut metrics = String::from(
+        "period,start,end,approach,throughput,completed,mean_delay,p95_delay,max_queue,max_wait\n",
+    );
+    let summary = simulation.metrics.summary(simulation.time());
+    let reports = simulation.metrics.reports.iter().enumerate();
+    for (period, report) in reports
+        .map(|(i, report)| (i.to_string(), report))
+        .chain([("run".to_string(), &summary)])
+    {
+        for (spawn, approach) in report.approaches.iter() {
+            metrics += &format!(
+                "{},{:.3},{:.3},{},{},{},{:.3},{:.3},{},{:.3}\n",
+                period,
+                report.start,
+                report.end,
+                spawn.as_str().to_lowercase(),
+                approach.throughput,
+                approach.completed,
+                approach.mean_delay,
+                approach.p95_delay,
+                approach.max_queue,
+                approach.max_wait
+            );
+        }
+    }
+    let path = output.join("metrics.csv");
+    fs::write(&path, metrics).map_err(|e| format!("cannot write '{}': {}", path.display(), e))?;
+
+    // Finished trips, then the ones still on the road
+    let mut trips = String::from(
+        "id,movement,class,requested,entered,junction_entry,junction_exit,finished,stops,\
+         stopped_time,travel_time,free_flow_time,delay,crashed\n",
+    );
+    let optional = |value: Option<f64>| value.map_or(String::new(), |v| format!("{:.3}", v));
+    let mut ongoing: Vec<&TripRecord> = simulation.metrics.ongoing().collect();
+    ongoing.sort_by_key(|trip| trip.id);
+    for trip in simulation.metrics.trips.iter().chain(ongoing) {
+        trips += &format!(
+            "{},{},{},{:.3},{:.3},{},{},{},{},{:.3},{},{:.3},{},{}\n",
+            trip.id,
+            trip.movement,
+            trip.class,
+            trip.requested,
+            trip.entered,
+            optional(trip.junction_entry),
+            optional(trip.junction_exit),
+            optional(trip.finished),
+            trip.stops,
+            trip.stopped_time,
+            optional(trip.travel_time().map(f64::from)),
+            trip.free_flow_time,
+            optional(trip.delay().map(f64::from)),
+            trip.crashed
+        );
     }
+    let path = output.join("trips.csv");
+    fs::write(&path, trips).map_err(|e| format!("cannot write '{}': {}", path.display(), e))
 }
@@ -1,125 +1,112 @@
-use crate::config::*;
-use crate::simulation::{TrafficLanes, TrafficLight, Vehicle};
+use crate::config::{Config, Direction, VehicleClass};
+use crate::simulation::{
+    ApproachMap, PEDESTRIAN_SIZE, PedestrianSignal, SignalState, Simulation, TrafficLight, Vehicle,
+    VehicleSpawn, VehicleState, WalkState,
+};
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
 use sdl2::render::WindowCanvas;
 use sdl2::video::Window;
 use std::cmp::{max, min};
-use std::collections::{HashMap, HashSet};
+
+// Lamp colors, lit and dimmed
+const RED_LAMP: (Color, Color) = (Color::RGB(255, 0, 0), Color::RGB(60, 0, 0));
+const AMBER_LAMP: (Color, Color) = (Color::RGB(255, 190, 0), Color::RGB(60, 45, 0));
+const GREEN_LAMP: (Color, Color) = (Color::RGB(0, 255, 0), Color::RGB(0, 60, 0));
+// Pedestrian signal colors, walk and don't walk, lit and dimmed
+const WALK_LAMP: (Color, Color) = (Color::RGB(255, 255, 255), Color::RGB(60, 60, 60));
+const DONT_WALK_LAMP: (Color, Color) = (Color::RGB(255, 110, 0), Color::RGB(60, 25, 0));
+// Size of the pedestrian signals drawn beyond both ends of a crosswalk
+const WALK_SIGNAL_SIZE: u32 = 8;
+// Zebra stripes of the crosswalks, width and period across the road
+const STRIPE_WIDTH: u32 = 5;
+const STRIPE_PERIOD: u32 = 10;
+
+// Parts drawn over the body of a vehicle, as (rear, front) fractions of its length from the
+// rear and the shade of the turn color they are drawn in
+fn class_details(class: VehicleClass) -> &'static [(f32, f32, f32)] {
+    match class {
+        // Windscreen
+        VehicleClass::Car => &[(0.55, 0.7, 0.5)],
+        // Cab in front of the trailer
+        VehicleClass::Truck => &[(0.68, 0.72, 0.2), (0.72, 1.0, 0.6)],
+        // Row of windows
+        VehicleClass::Bus => &[
+            (0.06, 0.16, 0.5),
+            (0.26, 0.36, 0.5),
+            (0.46, 0.56, 0.5),
+            (0.66, 0.76, 0.5),
+            (0.86, 0.96, 0.5),
+        ],
+        // Rider
+        VehicleClass::Motorcycle => &[(0.3, 0.6, 0.4)],
+        // Windscreen, the beacons are drawn behind it
+        VehicleClass::Ambulance => &[(0.68, 0.8, 0.5)],
+        // Ladder along the body, in front of the cab
+        VehicleClass::FireTruck => &[(0.05, 0.7, 0.7), (0.72, 1.0, 0.5)],
+    }
+}
+
+// Backlog counter drawn at the screen edge beside each approach, one pip per waiting vehicle
+// in the color of its turn, a white last pip when there are more
+const BACKLOG_PIP_SIZE: u32 = 6;
+const BACKLOG_PIP_GAP: u32 = 2;
+const BACKLOG_MAX_PIPS: usize = 20;
+
+// Body of the vehicles stopped by a crash, whatever their turn
+const WRECK_COLOR: (u8, u8, u8) = (140, 20, 20);
+
+// Beacons of the emergency vehicles, as (rear, front) fractions of its length from the rear,
+// alternating red and blue
+const BEACON: (f32, f32) = (0.6, 0.66);
+const BEACON_COLORS: [Color; 2] = [Color::RGB(255, 0, 0), Color::RGB(0, 80, 255)];
 
 pub struct Renderer {
     pub canvas: WindowCanvas,
-    pub lights: HashMap<String, TrafficLight>,
-    pub lanes: TrafficLanes,
-    pub waiting_lane: HashMap<String, HashMap<i32, Vehicle>>,
-    pub junction: HashSet<i32>,
-    pub active: String,
+    pub config: Config,
+    pub light_rects: ApproachMap<Rect>,
 }
 
 impl Renderer {
-    pub fn new(window: Window) -> Result<Renderer, String> {
-        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-        let lights: HashMap<String, TrafficLight> = HashMap::from([
-            (
-                String::from("North"),
-                TrafficLight::new(Rect::new(
-                    (GRID_WIDTH_PIXELS / 2 - 2 * CASE_SIZE - 1).cast_signed(),
-                    (GRID_HEIGHT_PIXELS / 2 - 2 * CASE_SIZE - 1).cast_signed(),
-                    CASE_SIZE,
-                    CASE_SIZE,
-                )),
-            ),
-            (
-                String::from("East"),
-                TrafficLight::new(Rect::new(
-                    (GRID_WIDTH_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-                    (GRID_HEIGHT_PIXELS / 2 - 2 * CASE_SIZE - 1).cast_signed(),
-                    CASE_SIZE,
-                    CASE_SIZE,
-                )),
-            ),
-            (
-                String::from("West"),
-                TrafficLight::new(Rect::new(
-                    (GRID_WIDTH_PIXELS / 2 - 2 * CASE_SIZE - 1).cast_signed(),
-                    (GRID_HEIGHT_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-                    CASE_SIZE,
-                    CASE_SIZE,
-                )),
-            ),
-            (
-                String::from("South"),
-                TrafficLight::new(Rect::new(
-                    (GRID_WIDTH_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-                    (GRID_HEIGHT_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-                    CASE_SIZE,
-                    CASE_SIZE,
-                )),
-            ),
-        ]);
+    pub fn new(window: Window, config: Config) -> Result<Renderer, String> {
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        // Scale the scenario canvas to whatever size the window is
+        canvas
+            .set_logical_size(config.canvas_width, config.canvas_height)
+            .map_err(|e| e.to_string())?;
+        let (grid_width, grid_height, case_size) = (
+            config.grid_width_pixels(),
+            config.grid_height_pixels(),
+            config.case_size(),
+        );
+        let road = config.road_width() as u32;
+        // On the corner of the sidewalk before the junction, on the right of the approach
+        let light_rects = ApproachMap::from_fn(|spawn| {
+            let (x, y) = match spawn {
+                VehicleSpawn::North => (
+                    grid_width / 2 - road - case_size - 1,
+                    grid_height / 2 - road - case_size - 1,
+                ),
+                VehicleSpawn::East => (
+                    grid_width / 2 + road + 1,
+                    grid_height / 2 - road - case_size - 1,
+                ),
+                VehicleSpawn::West => (
+                    grid_width / 2 - road - case_size - 1,
+                    grid_height / 2 + road + 1,
+                ),
+                VehicleSpawn::South => (grid_width / 2 + road + 1, grid_height / 2 + road + 1),
+            };
+            Rect::new(x.cast_signed(), y.cast_signed(), case_size, case_size)
+        });
 
-        let lanes = TrafficLanes::new();
-        let waiting_lane: HashMap<String, HashMap<i32, Vehicle>> = HashMap::from([
-            (String::from("South"), HashMap::new()),
-            (String::from("North"), HashMap::new()),
-            (String::from("East"), HashMap::new()),
-            (String::from("West"), HashMap::new()),
-        ]);
-        let junction: HashSet<i32> = HashSet::new();
-        let active = String::new();
         Ok(Renderer {
             canvas,
-            lights,
-            lanes,
-            waiting_lane,
-            junction,
-            active,
+            config,
+            light_rects,
         })
     }
 
-    pub fn update_lights(&mut self) {
-        // println!("{:?}", self.waiting_lane);
-        if !self.junction.is_empty() || !self.active.is_empty() {
-            if let Some(lane) = self.waiting_lane.get(&self.active)
-                && lane.is_empty()
-            {
-                if let Some(light) = self.lights.get_mut(&self.active) {
-                    light.change_state(Some(false));
-                    self.active.clear();
-                }
-            }
-            return;
-        }
-        let mut max_lane: Option<&String> = None;
-        let mut max_count = 0;
-
-        for (lane_name, vehicles) in self.waiting_lane.iter() {
-            let count = vehicles.len();
-            if count > 0 {
-                if count > max_count {
-                    max_count = count;
-                    max_lane = Some(lane_name);
-                }
-            }
-        }
-
-        for (lane_name, light) in self.lights.iter_mut() {
-            if let Some(max_lane_name) = max_lane {
-                if lane_name == max_lane_name {
-                    light.change_state(Some(true));
-                    self.active = lane_name.to_string();
-                } else {
-                    light.change_state(Some(false));
-                }
-            } else {
-                if self.active == lane_name.to_string() {
-                    self.active.clear()
-                }
-                light.change_state(Some(false));
-            }
-        }
-    }
-
     //Only for straight line
     fn draw_dotted_line(&mut self, start: (i32, i32), end: (i32, i32)) -> Result<(), String> {
         self.canvas.set_draw_color(Color::WHITE);
@@ -160,194 +147,313 @@ impl Renderer {
     }
 
     fn init_map(&mut self) -> Result<(), String> {
+        let (grid_width, grid_height) = (
+            self.config.grid_width_pixels(),
+            self.config.grid_height_pixels(),
+        );
+        let road = self.config.road_width() as u32;
         self.canvas.set_draw_color(Color::BLACK);
         self.canvas.clear();
 
+        // Sidewalk blocks in the corners
         self.canvas.set_draw_color(Color::GREY);
+        let (near_x, near_y) = (grid_width / 2 - road, grid_height / 2 - road);
+        let (far_x, far_y) = (
+            (grid_width / 2 + road + 1).cast_signed(),
+            (grid_height / 2 + road + 1).cast_signed(),
+        );
+        for (x, y) in [(-1, -1), (far_x, -1), (-1, far_y), (far_x, far_y)] {
+            self.canvas.draw_rect(Rect::new(x, y, near_x, near_y))?;
+        }
 
-        self.canvas.draw_rect(Rect::new(
-            -1,
-            -1,
-            GRID_WIDTH_PIXELS / 2 - CASE_SIZE,
-            GRID_HEIGHT_PIXELS / 2 - CASE_SIZE,
-        ))?;
-        self.canvas.draw_rect(Rect::new(
-            (GRID_WIDTH_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-            -1,
-            GRID_WIDTH_PIXELS / 2 - CASE_SIZE,
-            GRID_HEIGHT_PIXELS / 2 - CASE_SIZE,
-        ))?;
-        self.canvas.draw_rect(Rect::new(
-            -1,
-            (GRID_HEIGHT_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-            GRID_WIDTH_PIXELS / 2 - CASE_SIZE,
-            GRID_HEIGHT_PIXELS / 2 - CASE_SIZE,
-        ))?;
-        self.canvas.draw_rect(Rect::new(
-            (GRID_WIDTH_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-            (GRID_HEIGHT_PIXELS / 2 + CASE_SIZE + 1).cast_signed(),
-            GRID_WIDTH_PIXELS / 2 - CASE_SIZE,
-            GRID_HEIGHT_PIXELS / 2 - CASE_SIZE,
-        ))?;
+        // Center line and lane lines of both roads, up to the junction box
+        let (center_x, center_y) = (
+            (grid_width / 2).cast_signed(),
+            (grid_height / 2).cast_signed(),
+        );
+        let (road, lane) = (road.cast_signed(), self.config.lane_width.cast_signed());
+        for offset in (-road + lane..road).step_by(lane as usize) {
+            let (x, y) = (center_x + offset, center_y + offset);
+            self.draw_dotted_line((-5, y), (center_x - road, y))?;
+            self.draw_dotted_line((grid_width.cast_signed(), y), (center_x + road, y))?;
+            self.draw_dotted_line((x, -5), (x, center_y - road))?;
+            self.draw_dotted_line((x, grid_height.cast_signed()), (x, center_y + road))?;
+        }
 
-        self.draw_dotted_line(
-            (-5, (GRID_HEIGHT_PIXELS / 2).cast_signed()),
-            (
-                (GRID_WIDTH_PIXELS / 2 - CASE_SIZE).cast_signed(),
-                (GRID_HEIGHT_PIXELS / 2).cast_signed(),
-            ),
-        )?;
-        self.draw_dotted_line(
-            ((GRID_WIDTH_PIXELS / 2).cast_signed(), -5),
-            (
-                (GRID_WIDTH_PIXELS / 2).cast_signed(),
-                (GRID_HEIGHT_PIXELS / 2 - CASE_SIZE).cast_signed(),
-            ),
-        )?;
-        self.draw_dotted_line(
-            (
-                GRID_WIDTH_PIXELS.cast_signed(),
-                (GRID_HEIGHT_PIXELS / 2).cast_signed(),
-            ),
-            (
-                (GRID_WIDTH_PIXELS / 2 + CASE_SIZE).cast_signed(),
-                (GRID_HEIGHT_PIXELS / 2).cast_signed(),
-            ),
-        )?;
-        self.draw_dotted_line(
-            (
-                (GRID_WIDTH_PIXELS / 2).cast_signed(),
-                GRID_HEIGHT_PIXELS.cast_signed(),
-            ),
-            (
-                (GRID_WIDTH_PIXELS / 2).cast_signed(),
-                (GRID_HEIGHT_PIXELS / 2 + CASE_SIZE).cast_signed(),
-            ),
-        )?;
+        // Zebra crossings, stripes along the road
+        self.canvas.set_draw_color(Color::GREY);
+        for leg in VehicleSpawn::ALL {
+            let corners = self.config.crosswalk_area(leg);
+            let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min) as i32;
+            let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min) as i32;
+            let max_x = corners
+                .iter()
+                .map(|c| c.0)
+                .fold(f32::NEG_INFINITY, f32::max) as i32;
+            let max_y = corners
+                .iter()
+                .map(|c| c.1)
+                .fold(f32::NEG_INFINITY, f32::max) as i32;
+            let (width, height) = ((max_x - min_x) as u32, (max_y - min_y) as u32);
+            let vertical = leg.vector().0 == 0.0;
+            let across = if vertical { width } else { height };
+            for offset in (STRIPE_PERIOD / 2..across).step_by(STRIPE_PERIOD as usize) {
+                let offset = offset as i32 - STRIPE_WIDTH as i32 / 2;
+                self.canvas.fill_rect(if vertical {
+                    Rect::new(min_x + offset, min_y, STRIPE_WIDTH, height)
+                } else {
+                    Rect::new(min_x, min_y + offset, width, STRIPE_WIDTH)
+                })?;
+            }
+        }
 
         Ok(())
     }
 
-    pub fn draw_vehicles(&mut self) {
-        // Handle up lane vehicles
-        {
-            let mut up_lane = self.lanes.up.lock().unwrap();
-            let up_vehicles_clone = up_lane.clone(); // Clone for collision detection
-            let mut vehicles_to_remove = Vec::new();
-
-            for (index, vehicle) in up_lane.iter_mut().enumerate() {
-                let waiting = self.waiting_lane.get_mut("North").unwrap();
-                let should_remove = vehicle.r#move(
-                    &mut self.canvas,
-                    &self.lights,
-                    &up_vehicles_clone,
-                    waiting,
-                    &mut self.junction,
-                );
-                if should_remove {
-                    vehicles_to_remove.push(index);
-                }
+    pub fn draw_vehicles(&mut self, simulation: &Simulation) {
+        for lane in simulation.lanes.every_lane() {
+            for vehicle in lane.lock().unwrap().iter() {
+                self.draw_vehicle(vehicle, simulation.time());
             }
+        }
+    }
 
-            // Remove vehicles that reached destination (in reverse order to maintain indices)
-            for &index in vehicles_to_remove.iter().rev() {
-                up_lane.remove(index);
+    /// Vehicles waiting to enter each approach, as a row of pips along the screen edge on the
+    /// curb side of the road, from the road outwards
+    pub fn draw_backlogs(&mut self, simulation: &Simulation) -> Result<(), String> {
+        let (width, height) = (
+            self.config.canvas_width as i32,
+            self.config.canvas_height as i32,
+        );
+        let (cx, cy) = (width / 2, height / 2);
+        let road = self.config.road_width() as i32;
+        let (size, step) = (
+            BACKLOG_PIP_SIZE as i32,
+            (BACKLOG_PIP_SIZE + BACKLOG_PIP_GAP) as i32,
+        );
+        let margin = BACKLOG_PIP_GAP as i32;
+        for spawn in VehicleSpawn::ALL {
+            let backlog = simulation.lanes.backlog(spawn);
+            // First pip and offset to the next one
+            let (first, next) = match spawn {
+                VehicleSpawn::North => ((cx - road - margin - size, margin), (-step, 0)),
+                VehicleSpawn::South => ((cx + road + margin, height - margin - size), (step, 0)),
+                VehicleSpawn::East => (
+                    (width - margin - size, cy - road - margin - size),
+                    (0, -step),
+                ),
+                VehicleSpawn::West => ((margin, cy + road + margin), (0, step)),
+            };
+            for (i, pending) in backlog.iter().take(BACKLOG_MAX_PIPS).enumerate() {
+                let color = if i + 1 == BACKLOG_MAX_PIPS && backlog.len() > BACKLOG_MAX_PIPS {
+                    Color::WHITE
+                } else {
+                    let (r, g, b) = pending.direction.color();
+                    Color::RGB(r, g, b)
+                };
+                self.canvas.set_draw_color(color);
+                let i = i as i32;
+                self.canvas.fill_rect(Rect::new(
+                    first.0 + next.0 * i,
+                    first.1 + next.1 * i,
+                    BACKLOG_PIP_SIZE,
+                    BACKLOG_PIP_SIZE,
+                ))?;
             }
         }
+        Ok(())
+    }
 
-        // Handle bottom lane vehicles
-        {
-            let mut bottom_lane = self.lanes.bottom.lock().unwrap();
-            let bottom_vehicles_clone = bottom_lane.clone();
-            let mut vehicles_to_remove = Vec::new();
-
-            for (index, vehicle) in bottom_lane.iter_mut().enumerate() {
-                let waiting = self.waiting_lane.get_mut("South").unwrap();
-                let should_remove = vehicle.r#move(
-                    &mut self.canvas,
-                    &self.lights,
-                    &bottom_vehicles_clone,
-                    waiting,
-                    &mut self.junction,
-                );
-                if should_remove {
-                    vehicles_to_remove.push(index);
-                }
-            }
+    /// Pedestrians as small squares, on the curb or on the crosswalk
+    pub fn draw_pedestrians(&mut self, simulation: &Simulation) -> Result<(), String> {
+        self.canvas.set_draw_color(Color::RGB(200, 200, 255));
+        let size = PEDESTRIAN_SIZE as u32;
+        for pedestrian in &simulation.pedestrians {
+            let (x, y) = pedestrian.position(&self.config);
+            let half = PEDESTRIAN_SIZE / 2.0;
+            self.canvas.fill_rect(Rect::new(
+                (x - half).round() as i32,
+                (y - half).round() as i32,
+                size,
+                size,
+            ))?;
+        }
+        Ok(())
+    }
 
-            for &index in vehicles_to_remove.iter().rev() {
-                bottom_lane.remove(index);
+    // Walk in white, don't walk in orange, beyond both curbs of the crosswalk
+    fn draw_walk_signal(
+        &mut self,
+        leg: VehicleSpawn,
+        signal: &PedestrianSignal,
+        time: f64,
+    ) -> Result<(), String> {
+        let blink = time.fract() < 0.5;
+        let (walk, dont_walk) = match signal.state() {
+            WalkState::Walk => (true, false),
+            WalkState::FlashingDontWalk => (false, blink),
+            WalkState::DontWalk => (false, true),
+        };
+        let (a, b) = self.config.crosswalk_ends(leg);
+        let length = self.config.crossing_length();
+        let (dx, dy) = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+        let beyond = PEDESTRIAN_SIZE + WALK_SIGNAL_SIZE as f32;
+        let half = WALK_SIGNAL_SIZE as f32 / 2.0;
+        for (x, y) in [
+            (a.0 - dx * beyond, a.1 - dy * beyond),
+            (b.0 + dx * beyond, b.1 + dy * beyond),
+        ] {
+            // Walk lamp towards the crosswalk, don't walk lamp beside it
+            let (x, y) = ((x - half).round() as i32, (y - half).round() as i32);
+            let (ox, oy) = (
+                (dy.abs() * half * 2.0) as i32,
+                (dx.abs() * half * 2.0) as i32,
+            );
+            for (lit, colors, (x, y)) in [
+                (walk, WALK_LAMP, (x, y)),
+                (dont_walk, DONT_WALK_LAMP, (x + ox, y + oy)),
+            ] {
+                self.canvas
+                    .set_draw_color(if lit { colors.0 } else { colors.1 });
+                self.canvas
+                    .fill_rect(Rect::new(x, y, WALK_SIGNAL_SIZE, WALK_SIGNAL_SIZE))?;
             }
         }
+        Ok(())
+    }
 
-        // Handle left lane vehicles
-        {
-            let mut left_lane = self.lanes.left.lock().unwrap();
-            let left_vehicles_clone = left_lane.clone();
-            let mut vehicles_to_remove = Vec::new();
-
-            for (index, vehicle) in left_lane.iter_mut().enumerate() {
-                let waiting = self.waiting_lane.get_mut("West").unwrap();
-                let should_remove = vehicle.r#move(
-                    &mut self.canvas,
-                    &self.lights,
-                    &left_vehicles_clone,
-                    waiting,
-                    &mut self.junction,
-                );
-                if should_remove {
-                    vehicles_to_remove.push(index);
-                }
-            }
+    // Body in the color of the turn, with the details of its class drawn over it
+    // Crashed vehicles are drawn in dark red
+    fn draw_vehicle(&mut self, vehicle: &Vehicle, time: f64) {
+        let (r, g, b) = match vehicle.state {
+            VehicleState::Crashed => WRECK_COLOR,
+            _ => vehicle.direction.color(),
+        };
+        self.fill_vehicle_part(vehicle, 0.0, 1.0, Color::RGB(r, g, b));
+        for &(rear, front, shade) in class_details(vehicle.class) {
+            let dim = |c: u8| (c as f32 * shade) as u8;
+            self.fill_vehicle_part(vehicle, rear, front, Color::RGB(dim(r), dim(g), dim(b)));
+        }
+        // Beacons switch color four times per second
+        if vehicle.class.is_emergency() {
+            let color = BEACON_COLORS[(time * 4.0) as usize % 2];
+            self.fill_vehicle_part(vehicle, BEACON.0, BEACON.1, color);
+        }
+    }
 
-            for &index in vehicles_to_remove.iter().rev() {
-                left_lane.remove(index);
-            }
+    // Part of a vehicle between two fractions of its length, from the rear
+    // Filled with lines from the rear to the front, half a pixel apart across the vehicle,
+    // so that it stays solid at any heading along the curve of a turn
+    fn fill_vehicle_part(&mut self, vehicle: &Vehicle, rear: f32, front: f32, color: Color) {
+        let [front_left, front_right, rear_right, rear_left] = vehicle.corners();
+        let along = |from: (f32, f32), to: (f32, f32), t: f32| {
+            (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+        };
+        let (rear_left, rear_right, front_left, front_right) = (
+            along(rear_left, front_left, rear),
+            along(rear_right, front_right, rear),
+            along(rear_left, front_left, front),
+            along(rear_right, front_right, front),
+        );
+        self.canvas.set_draw_color(color);
+        let steps = vehicle.width * 2;
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            let point = |a: (f32, f32), b: (f32, f32)| {
+                let (x, y) = along(a, b, t);
+                Point::new(x.round() as i32, y.round() as i32)
+            };
+            let _ = self
+                .canvas
+                .draw_line(point(rear_left, rear_right), point(front_left, front_right));
         }
+    }
 
-        // Handle right lane vehicles
-        {
-            let mut right_lane = self.lanes.right.lock().unwrap();
-            let right_vehicles_clone = right_lane.clone();
-            let mut vehicles_to_remove = Vec::new();
+    // One column of three lamps per head, left turn, straight and right turn
+    // Lamps are stacked red on top, amber, then green, the left turn ones are arrows
+    // A permissive left turn shows a flashing amber arrow
+    fn draw_light(&mut self, rect: Rect, light: &TrafficLight, time: f64) -> Result<(), String> {
+        // Flashing amber blinks once per second
+        let blink = time.fract() < 0.5;
+
+        self.canvas.set_draw_color(Color::GREY);
+        self.canvas.draw_rect(rect)?;
 
-            for (index, vehicle) in right_lane.iter_mut().enumerate() {
-                let waiting = self.waiting_lane.get_mut("East").unwrap();
-                let should_remove = vehicle.r#move(
-                    &mut self.canvas,
-                    &self.lights,
-                    &right_vehicles_clone,
-                    waiting,
-                    &mut self.junction,
-                );
-                if should_remove {
-                    vehicles_to_remove.push(index);
+        let lamp_width = rect.width() / 3;
+        let lamp_height = rect.height() / 3;
+        for (column, turn) in Direction::ALL.into_iter().enumerate() {
+            let head = light.head(turn);
+            let (red, amber, green) = match head.state() {
+                SignalState::Red => (true, false, false),
+                SignalState::RedAmber => (true, true, false),
+                SignalState::Green if head.is_permissive() => (false, blink, false),
+                SignalState::Green => (false, false, true),
+                SignalState::Amber => (false, true, false),
+                SignalState::FlashingAmber => (false, blink, false),
+                SignalState::Off => (false, false, false),
+            };
+            let x = rect.x() + (column as u32 * lamp_width) as i32;
+            for (row, (lit, colors)) in [(red, RED_LAMP), (amber, AMBER_LAMP), (green, GREEN_LAMP)]
+                .into_iter()
+                .enumerate()
+            {
+                let y = rect.y() + (row as u32 * lamp_height) as i32;
+                let lamp = Rect::new(x + 2, y + 2, lamp_width - 4, lamp_height - 4);
+                self.canvas
+                    .set_draw_color(if lit { colors.0 } else { colors.1 });
+                match turn {
+                    Direction::Left => self.draw_left_arrow(lamp)?,
+                    _ => self.canvas.fill_rect(lamp)?,
                 }
             }
+        }
 
-            for &index in vehicles_to_remove.iter().rev() {
-                right_lane.remove(index);
-            }
+        Ok(())
+    }
+
+    // Arrow pointing left filling the rect, head on the left half, shaft on the right one
+    fn draw_left_arrow(&mut self, rect: Rect) -> Result<(), String> {
+        let half = rect.height() as i32 / 2;
+        let center = rect.y() + half;
+        for i in 0..half.min(rect.width() as i32) {
+            self.canvas.draw_line(
+                Point::new(rect.x() + i, center - i),
+                Point::new(rect.x() + i, center + i),
+            )?;
         }
+        let shaft_x = rect.x() + half.min(rect.width() as i32);
+        let shaft_width = (rect.right() - shaft_x).max(0) as u32;
+        self.canvas.fill_rect(Rect::new(
+            shaft_x,
+            center - half / 3,
+            shaft_width,
+            (2 * (half / 3)).max(1) as u32,
+        ))?;
+        Ok(())
     }
 
-    // Method to draw the MAP - LIGHTS
-    pub fn draw(&mut self) -> Result<(), String> {
+    /// Method to draw the MAP - LIGHTS
+    pub fn draw(&mut self, simulation: &Simulation) -> Result<(), String> {
         self.init_map()?;
-        self.update_lights();
-        for light in self.lights.values_mut() {
-            light.draw(&mut self.canvas)?;
+        for (spawn, light) in simulation.lights.iter() {
+            self.draw_light(self.light_rects[spawn], light, simulation.time())?;
+        }
+        for (leg, signal) in simulation.crosswalks.iter() {
+            self.draw_walk_signal(leg, signal, simulation.time())?;
         }
 
         Ok(())
     }
 
-    // Debug method to print the lane's vehicles
-    pub fn _print_debug_info(&self) {
-        let (up, bottom, left, right) = self.lanes._get_lane_counts();
+    /// Debug method to print the lane's vehicles
+    pub fn _print_debug_info(simulation: &Simulation) {
+        let counts = simulation.lanes.get_lane_counts();
         println!(
             "Vehicle counts - Up: {}, Bottom: {}, Left: {}, Right: {}",
-            up, bottom, left, right
+            counts[VehicleSpawn::North],
+            counts[VehicleSpawn::South],
+            counts[VehicleSpawn::West],
+            counts[VehicleSpawn::East]
         );
     }
 }
@@ -0,0 +1,246 @@
+use crate::config::{BOTTOM_VECTOR, Config, Direction, LEFT_VECTOR, RIGHT_VECTOR, TOP_VECTOR};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+/// Side of the intersection a vehicle comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VehicleSpawn {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl VehicleSpawn {
+    /// Every approach, in the order used to break ties
+    pub const ALL: [VehicleSpawn; 4] = [
+        VehicleSpawn::North,
+        VehicleSpawn::South,
+        VehicleSpawn::East,
+        VehicleSpawn::West,
+    ];
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            VehicleSpawn::North => 0,
+            VehicleSpawn::South => 1,
+            VehicleSpawn::East => 2,
+            VehicleSpawn::West => 3,
+        }
+    }
+
+    /// Approach facing this one across the junction
+    pub fn opposite(self) -> VehicleSpawn {
+        match self {
+            VehicleSpawn::North => VehicleSpawn::South,
+            VehicleSpawn::South => VehicleSpawn::North,
+            VehicleSpawn::East => VehicleSpawn::West,
+            VehicleSpawn::West => VehicleSpawn::East,
+        }
+    }
+
+    /// Initial heading of the vehicles coming from this side
+    pub fn vector(&self) -> (f32, f32) {
+        match self {
+            VehicleSpawn::North => BOTTOM_VECTOR,
+            VehicleSpawn::South => TOP_VECTOR,
+            VehicleSpawn::East => LEFT_VECTOR,
+            VehicleSpawn::West => RIGHT_VECTOR,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            VehicleSpawn::North => "North",
+            VehicleSpawn::South => "South",
+            VehicleSpawn::East => "East",
+            VehicleSpawn::West => "West",
+        }
+    }
+}
+
+/// Fixed size storage with one value per approach, indexed by `VehicleSpawn`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApproachMap<T>([T; 4]);
+
+impl<T> ApproachMap<T> {
+    pub fn from_fn(mut f: impl FnMut(VehicleSpawn) -> T) -> Self {
+        Self(VehicleSpawn::ALL.map(&mut f))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (VehicleSpawn, &T)> {
+        VehicleSpawn::ALL
+            .into_iter()
+            .map(|spawn| (spawn, &self[spawn]))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (VehicleSpawn, &mut T)> {
+        VehicleSpawn::ALL.into_iter().zip(self.0.iter_mut())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+// Written as a table keyed by approach in scenario files, e.g. `[class_mix.north]`
+// Missing approaches keep the default value
+impl<'de, T: Deserialize<'de> + Default> Deserialize<'de> for ApproachMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Approaches<T> {
+            north: Option<T>,
+            south: Option<T>,
+            east: Option<T>,
+            west: Option<T>,
+        }
+
+        let mut approaches = Approaches::deserialize(deserializer)?;
+        Ok(ApproachMap::from_fn(|spawn| {
+            match spawn {
+                VehicleSpawn::North => approaches.north.take(),
+                VehicleSpawn::South => approaches.south.take(),
+                VehicleSpawn::East => approaches.east.take(),
+                VehicleSpawn::West => approaches.west.take(),
+            }
+            .unwrap_or_default()
+        }))
+    }
+}
+
+impl<T> Index<VehicleSpawn> for ApproachMap<T> {
+    type Output = T;
+
+    fn index(&self, spawn: VehicleSpawn) -> &T {
+        &self.0[spawn.index()]
+    }
+}
+
+impl<T> IndexMut<VehicleSpawn> for ApproachMap<T> {
+    fn index_mut(&mut self, spawn: VehicleSpawn) -> &mut T {
+        &mut self.0[spawn.index()]
+    }
+}
+
+/// What a vehicle does at the intersection: where it comes from and where it turns
+/// Written `"<approach>-<turn>"` in scenario files, e.g. "north-left" or "east-up"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Movement {
+    pub from: VehicleSpawn,
+    pub turn: Direction,
+}
+
+impl Movement {
+    pub fn new(from: VehicleSpawn, turn: Direction) -> Self {
+        Self { from, turn }
+    }
+
+    /// Every movement, approach by approach
+    pub fn all() -> impl Iterator<Item = Movement> {
+        VehicleSpawn::ALL
+            .into_iter()
+            .flat_map(|from| Direction::ALL.map(|turn| Movement::new(from, turn)))
+    }
+
+    fn index(self) -> usize {
+        self.from.index() * Direction::ALL.len() + self.turn.index()
+    }
+
+    /// Side through which the vehicles doing this movement leave the intersection
+    pub fn exit(&self) -> VehicleSpawn {
+        match self.from {
+            VehicleSpawn::North => match self.turn {
+                Direction::Up => VehicleSpawn::South,
+                Direction::Left => VehicleSpawn::East,
+                Direction::Right => VehicleSpawn::West,
+            },
+            VehicleSpawn::South => match self.turn {
+                Direction::Up => VehicleSpawn::North,
+                Direction::Left => VehicleSpawn::West,
+                Direction::Right => VehicleSpawn::East,
+            },
+            VehicleSpawn::East => match self.turn {
+                Direction::Up => VehicleSpawn::West,
+                Direction::Left => VehicleSpawn::South,
+                Direction::Right => VehicleSpawn::North,
+            },
+            VehicleSpawn::West => match self.turn {
+                Direction::Up => VehicleSpawn::East,
+                Direction::Left => VehicleSpawn::North,
+                Direction::Right => VehicleSpawn::South,
+            },
+        }
+    }
+
+    /// Point where the vehicles doing this movement from the given lane leave the screen, they
+    /// keep the rank of their lane
+    pub fn destination(&self, lane: usize, config: &Config) -> (f32, f32) {
+        config.destination(self.exit(), lane)
+    }
+
+    /// Whether this movement cannot be green while the crosswalk of `leg` shows walk: its
+    /// vehicles enter the junction through it, or go straight through it
+    /// Turning vehicles leaving through a crosswalk yield to its pedestrians instead
+    pub fn conflicts_with_crosswalk(&self, leg: VehicleSpawn) -> bool {
+        self.from == leg || (self.exit() == leg && self.turn == Direction::Up)
+    }
+}
+
+impl fmt::Display for Movement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.from.as_str().to_lowercase(),
+            self.turn.as_str()
+        )
+    }
+}
+
+impl TryFrom<String> for Movement {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        Movement::all()
+            .find(|movement| movement.to_string() == name)
+            .ok_or_else(|| {
+                format!("unknown movement '{name}', expected e.g. \"north-left\" or \"east-up\"")
+            })
+    }
+}
+
+/// Fixed size storage with one value per movement, indexed by `Movement`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MovementMap<T>([T; 12]);
+
+impl<T> MovementMap<T> {
+    pub fn from_fn(mut f: impl FnMut(Movement) -> T) -> Self {
+        let mut movements = Movement::all();
+        Self(std::array::from_fn(|_| f(movements.next().unwrap())))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Movement, &T)> {
+        Movement::all().zip(self.0.iter())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+impl<T> Index<Movement> for MovementMap<T> {
+    type Output = T;
+
+    fn index(&self, movement: Movement) -> &T {
+        &self.0[movement.index()]
+    }
+}
+
+impl<T> IndexMut<Movement> for MovementMap<T> {
+    fn index_mut(&mut self, movement: Movement) -> &mut T {
+        &mut self.0[movement.index()]
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::MAX_STEPS_PER_FRAME;
+use std::time::Duration;
+
+/// Fixed timestep clock - the simulation always advances by `dt` seconds,
+/// real time is only accumulated to know how many steps are due
+#[derive(Debug, Clone)]
+pub struct SimulationClock {
+    pub dt: f32,
+    pub ticks: u64,
+    accumulator: f32,
+}
+
+impl SimulationClock {
+    pub fn new(dt: f32) -> Self {
+        Self {
+            dt,
+            ticks: 0,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Simulated time in seconds
+    pub fn time(&self) -> f64 {
+        self.ticks as f64 * self.dt as f64
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Add real elapsed time and return the number of steps to run now
+    pub fn accumulate(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.as_secs_f32();
+
+        let mut steps = 0;
+        while self.accumulator >= self.dt && steps < MAX_STEPS_PER_FRAME {
+            self.accumulator -= self.dt;
+            steps += 1;
+        }
+        // Drop the time we could not catch up with instead of piling it up
+        if steps == MAX_STEPS_PER_FRAME {
+            self.accumulator = self.accumulator.min(self.dt);
+        }
+        steps
+    }
+}
@@ -0,0 +1,204 @@
+use crate::simulation::{Movement, Vehicle, overlap_depth};
+use std::collections::HashSet;
+
+// Footprints overlapping by more than this many pixels are in contact
+const CONTACT_DEPTH: f32 = 1.0;
+
+/// Two vehicles that ran into each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crash {
+    /// Simulated time of the first contact, in seconds
+    pub time: f64,
+    /// Ids of both vehicles, the first spawned one first
+    pub vehicles: [i32; 2],
+    pub movements: [Movement; 2],
+    /// Halfway between the centers of both vehicles
+    pub position: (f32, f32),
+}
+
+/// Checks every pair of vehicles for overlapping footprints, whatever their lanes
+/// Two vehicles staying in contact over several steps are a single crash
+#[derive(Debug, Clone, Default)]
+pub struct CollisionDetector {
+    // Pairs of vehicles in contact at the last check
+    contacts: HashSet<[i32; 2]>,
+    /// Every crash since the start
+    pub crashes: Vec<Crash>,
+}
+
+impl CollisionDetector {
+    /// Record the contacts started since the last check and return them
+    pub fn check(&mut self, vehicles: &[Vehicle], time: f64) -> Vec<Crash> {
+        let mut contacts = HashSet::new();
+        let mut crashes = Vec::new();
+        for (i, a) in vehicles.iter().enumerate() {
+            let footprint = a.corners();
+            for b in &vehicles[i + 1..] {
+                if overlap_depth(&footprint, &b.corners()) <= CONTACT_DEPTH {
+                    continue;
+                }
+                let (first, second) = if a.id < b.id { (a, b) } else { (b, a) };
+                let pair = [first.id, second.id];
+                contacts.insert(pair);
+                if self.contacts.contains(&pair) {
+                    continue;
+                }
+                crashes.push(Crash {
+                    time,
+                    vehicles: pair,
+                    movements: [first.movement(), second.movement()],
+                    position: (
+                        (first.position.0 + second.position.0) / 2.0,
+                        (first.position.1 + second.position.1) / 2.0,
+                    ),
+                });
+            }
+        }
+        self.contacts = contacts;
+        self.crashes.extend_from_slice(&crashes);
+        crashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Direction, VehicleClass};
+    use crate::simulation::{EventKind, Simulation, VehicleSpawn, VehicleState};
+
+    // Square of the given half size around a center, turned by 45 degrees when `diamond`
+    fn square(center: (f32, f32), half: f32, diamond: bool) -> [(f32, f32); 4] {
+        let (x, y) = center;
+        if diamond {
+            [(x, y - half), (x + half, y), (x, y + half), (x - half, y)]
+        } else {
+            [
+                (x - half, y - half),
+                (x + half, y - half),
+                (x + half, y + half),
+                (x - half, y + half),
+            ]
+        }
+    }
+
+    fn car(id: i32, position: (f32, f32), config: &Config) -> Vehicle {
+        let mut vehicle = Vehicle::new(
+            id,
+            VehicleClass::Car,
+            Direction::Up,
+            VehicleSpawn::North,
+            0,
+            config,
+        );
+        vehicle.position = position;
+        vehicle
+    }
+
+    // Simulation with a single vehicle on the north approach and a copy of it, in the same
+    // place, so that they collide in the first step
+    fn pile_up(config: Config) -> Simulation {
+        let mut simulation = Simulation::new(config, 1);
+        let id = simulation
+            .spawn_vehicle_of_class(VehicleSpawn::North, VehicleClass::Car, Some(Direction::Up))
+            .unwrap();
+        let mut lane = simulation.lanes.lanes[VehicleSpawn::North][0]
+            .lock()
+            .unwrap();
+        let mut copy = lane
+            .iter()
+            .find(|vehicle| vehicle.id == id)
+            .unwrap()
+            .clone();
+        copy.id = 1000;
+        lane.push_back(copy);
+        drop(lane);
+        simulation
+    }
+
+    #[test]
+    fn overlap_depth_separates_turned_boxes() {
+        let base = square((5.0, 5.0), 5.0, false);
+        // Side by side, 2 pixels into each other
+        assert_eq!(overlap_depth(&base, &square((13.0, 5.0), 5.0, false)), 2.0);
+        assert!(overlap_depth(&base, &square((16.0, 5.0), 5.0, false)) <= 0.0);
+        // Bounding boxes overlap, but the diamond passes by the corner of the square
+        assert!(overlap_depth(&base, &square((17.0, 17.0), 10.0, true)) <= 0.0);
+        // The diamond tip goes into the corner, as deep as along the diagonal
+        let depth = overlap_depth(&base, &square((14.0, 14.0), 10.0, true));
+        assert!((depth - 2f32.sqrt()).abs() < 1e-4, "depth {depth}");
+    }
+
+    #[test]
+    fn contact_is_a_single_crash_until_the_vehicles_part() {
+        let config = Config::default();
+        let mut detector = CollisionDetector::default();
+        let a = car(1, (100.0, 100.0), &config);
+        let mut b = car(2, (100.0, 110.0), &config);
+        let crashes = detector.check(&[b.clone(), a.clone()], 3.0);
+        assert_eq!(
+            crashes,
+            [Crash {
+                time: 3.0,
+                vehicles: [1, 2],
+                movements: [a.movement(), b.movement()],
+                position: (100.0, 105.0),
+            }]
+        );
+        assert!(detector.check(&[a.clone(), b.clone()], 3.5).is_empty());
+        b.position = (100.0, 500.0);
+        assert!(detector.check(&[a.clone(), b.clone()], 4.0).is_empty());
+        b.position = (100.0, 110.0);
+        assert_eq!(detector.check(&[a, b], 4.5).len(), 1);
+        assert_eq!(detector.crashes.len(), 2);
+    }
+
+    #[test]
+    fn crashed_vehicles_stay_put_until_cleared() {
+        let config = Config {
+            crash_clearance_time: 2.0,
+            ..Config::default()
+        };
+        let mut simulation = pile_up(config);
+        simulation.step();
+        let crashes = simulation.crashes().to_vec();
+        assert_eq!(crashes.len(), 1);
+        assert_eq!(crashes[0].vehicles, [1, 1000]);
+        assert_eq!(crashes[0].time, 0.0);
+        assert!(
+            simulation
+                .drain_events()
+                .iter()
+                .any(|event| event.kind == EventKind::VehicleCrashed(crashes[0]))
+        );
+
+        let wrecks = simulation.vehicles();
+        assert!(
+            wrecks
+                .iter()
+                .all(|vehicle| vehicle.state == VehicleState::Crashed)
+        );
+        while simulation.time() < 1.9 {
+            simulation.step();
+            let vehicles = simulation.vehicles();
+            assert_eq!(vehicles.len(), 2);
+            for (vehicle, wreck) in vehicles.iter().zip(&wrecks) {
+                assert_eq!(vehicle.position, wreck.position);
+            }
+        }
+        while simulation.time() < 2.1 {
+            simulation.step();
+        }
+        assert_eq!(simulation.vehicle_count(), 0);
+        assert_eq!(simulation.crashes().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "vehicles 1 (north-up) and 1000 (north-up) collided")]
+    fn strict_collisions_panic_on_the_first_crash() {
+        let config = Config {
+            strict_collisions: true,
+            ..Config::default()
+        };
+        pile_up(config).step();
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::{Config, VehicleClass};
+use crate::simulation::{Footprint, Movement, MovementMap, Vehicle, overlap_depth, sweep_junction};
+use std::collections::{HashMap, HashSet};
+
+// Box (min x, min y, max x, max y) of a conflict zone
+type Zone = (f32, f32, f32, f32);
+
+// Movement, lane and class of a vehicle crossing the junction
+type Sweep = (Movement, usize, VehicleClass);
+
+// Sweeps of two lanes are compared every this many pixels
+const SWING_STEP: usize = 4;
+
+/// Movements of the vehicles currently inside each conflict zone, indexed like the zones
+pub type ZoneOccupancy = Vec<Vec<Movement>>;
+
+/// Which movements cannot be served at the same time, and where they cross
+/// The junction box is cut in one lane wide square zones, every movement sweeps a few of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflictMatrix {
+    zones: Vec<Zone>,
+    // Zones swept by the vehicles doing each movement
+    movement_zones: MovementMap<Vec<usize>>,
+    // Footprints of a vehicle of a class doing a movement from a lane, across the junction
+    sweeps: HashMap<Sweep, Vec<Footprint>>,
+    // Pairs of sweeps from two lanes of an approach that touch
+    swings: HashSet<(Sweep, Sweep)>,
+    conflicts: MovementMap<MovementMap<bool>>,
+}
+
+impl ConflictMatrix {
+    /// Matrix given by `config.conflicts`, derived from the geometry when not set
+    /// Derived: two movements conflict when they come from different approaches and share a zone
+    pub fn new(config: &Config) -> Self {
+        let (min_x, min_y, max_x, max_y) = config.junction_box();
+        let size = config.lane_width as f32;
+        let mut zones = Vec::new();
+        let mut y = min_y;
+        while y < max_y {
+            let mut x = min_x;
+            while x < max_x {
+                zones.push((x, y, x + size, y + size));
+                x += size;
+            }
+            y += size;
+        }
+
+        // Swept from every lane the movement is allowed from, by every class spawned on the
+        // approach and by the emergency vehicles
+        let mut sweeps = HashMap::new();
+        for movement in Movement::all() {
+            let uses = config.lane_uses(movement.from);
+            let lanes = (0..uses.len()).filter(|&lane| uses[lane].allows(movement.turn));
+            for lane in lanes {
+                let classes = config.class_mix[movement.from]
+                    .classes()
+                    .chain(VehicleClass::EMERGENCY);
+                for class in classes {
+                    let footprints = sweep_junction(movement, lane, class, config, 1.0);
+                    sweeps.insert((movement, lane, class), footprints);
+                }
+            }
+        }
+
+        // Long vehicles swing wider in turns
+        let movement_zones = MovementMap::from_fn(|movement| {
+            let footprints: Vec<&Footprint> = sweeps
+                .iter()
+                .filter(|((m, _, class), _)| {
+                    *m == movement
+                        && config.class_mix[movement.from]
+                            .classes()
+                            .any(|c| c == *class)
+                })
+                .flat_map(|(_, footprints)| footprints)
+                .collect();
+            (0..zones.len())
+                .filter(|&zone| {
+                    footprints
+                        .iter()
+                        .any(|footprint| overlaps(zones[zone], footprint))
+                })
+                .collect::<Vec<usize>>()
+        });
+
+        // Sweeps of two lanes of an approach touching, checked every few pixels
+        let mut swings = HashSet::new();
+        for (&a, sweep_a) in &sweeps {
+            for (&b, sweep_b) in &sweeps {
+                let (movement_a, lane_a, _) = a;
+                let (movement_b, lane_b, _) = b;
+                if movement_a.from != movement_b.from || lane_a >= lane_b {
+                    continue;
+                }
+                let touching = sweep_a.iter().step_by(SWING_STEP).any(|footprint_a| {
+                    sweep_b
+                        .iter()
+                        .step_by(SWING_STEP)
+                        .any(|footprint_b| overlap_depth(footprint_a, footprint_b) > 1.0)
+                });
+                if touching {
+                    swings.insert((a, b));
+                    swings.insert((b, a));
+                }
+            }
+        }
+
+        let conflicts = match &config.conflicts {
+            Some(pairs) => MovementMap::from_fn(|a| {
+                MovementMap::from_fn(|b| {
+                    pairs
+                        .iter()
+                        .any(|&(x, y)| (x == a && y == b) || (x == b && y == a))
+                })
+            }),
+            None => MovementMap::from_fn(|a: Movement| {
+                MovementMap::from_fn(|b: Movement| {
+                    a.from != b.from
+                        && movement_zones[a]
+                            .iter()
+                            .any(|zone| movement_zones[b].contains(zone))
+                })
+            }),
+        };
+
+        ConflictMatrix {
+            zones,
+            movement_zones,
+            sweeps,
+            swings,
+            conflicts,
+        }
+    }
+
+    pub fn conflicts(&self, a: Movement, b: Movement) -> bool {
+        self.conflicts[a][b]
+    }
+
+    /// Whether `movement` can be served together with every movement of `phase`
+    pub fn compatible(&self, movement: Movement, phase: &[Movement]) -> bool {
+        phase.iter().all(|&other| !self.conflicts(movement, other))
+    }
+
+    pub fn zone_count(&self) -> usize {
+        self.zones.len()
+    }
+
+    /// Zones swept by the vehicles doing this movement
+    pub fn zones(&self, movement: Movement) -> &[usize] {
+        &self.movement_zones[movement]
+    }
+
+    /// Whether vehicles of two lanes of an approach would touch crossing the junction side by
+    /// side, a long vehicle turning swings across the next lane
+    pub fn swings_into(&self, a: &Vehicle, b: &Vehicle) -> bool {
+        self.swings.contains(&(sweep(a), sweep(b)))
+    }
+
+    /// Whether a vehicle crossing the junction from its stop line would touch this footprint
+    pub fn sweep_touches(&self, vehicle: &Vehicle, footprint: &Footprint) -> bool {
+        self.sweeps.get(&sweep(vehicle)).is_some_and(|sweep| {
+            sweep
+                .iter()
+                .any(|swept| overlap_depth(swept, footprint) > 1.0)
+        })
+    }
+
+    /// Zones overlapped by a vehicle footprint
+    pub fn zones_at<'a>(&'a self, footprint: &'a Footprint) -> impl Iterator<Item = usize> + 'a {
+        (0..self.zones.len()).filter(move |&zone| overlaps(self.zones[zone], footprint))
+    }
+
+    /// No vehicle of a conflicting movement is left in any zone, a vehicle out of the zones of
+    /// `movement` may still be on its way to them
+    /// With `yielding`, the vehicles of the opposing approach are not waited for
+    pub fn is_clear(&self, movement: Movement, occupancy: &ZoneOccupancy, yielding: bool) -> bool {
+        occupancy.iter().flatten().all(|&other| {
+            !self.conflicts(movement, other) || (yielding && other.from == movement.from.opposite())
+        })
+    }
+}
+
+fn sweep(vehicle: &Vehicle) -> Sweep {
+    (vehicle.movement(), vehicle.lane, vehicle.class)
+}
+
+// Overlap of more than a pixel, so vehicles touching a zone edge are not in it
+fn overlaps(zone: Zone, footprint: &Footprint) -> bool {
+    let (min_x, min_y, max_x, max_y) = zone;
+    let corners = [
+        (min_x, min_y),
+        (max_x, min_y),
+        (max_x, max_y),
+        (min_x, max_y),
+    ];
+    overlap_depth(&corners, footprint) > 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Direction::{Left, Right, Up};
+    use crate::simulation::VehicleSpawn::{East, North, South, West};
+
+    #[test]
+    fn crossing_paths_conflict_and_parallel_ones_do_not() {
+        let conflicts = ConflictMatrix::new(&Config::default());
+        let conflict = |a, b| conflicts.conflicts(a, b);
+        let north_up = Movement::new(North, Up);
+        assert!(conflict(north_up, Movement::new(East, Up)));
+        assert!(conflict(north_up, Movement::new(West, Up)));
+        assert!(conflict(north_up, Movement::new(South, Left)));
+        assert!(!conflict(north_up, Movement::new(South, Up)));
+        assert!(!conflict(north_up, Movement::new(South, Right)));
+        assert!(!conflict(
+            Movement::new(North, Right),
+            Movement::new(South, Right)
+        ));
+        for a in Movement::all() {
+            for b in Movement::all() {
+                assert_eq!(conflict(a, b), conflict(b, a), "{a} and {b}");
+                if a.from == b.from {
+                    assert!(!conflict(a, b), "{a} and {b} come from the same approach");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn given_pairs_replace_the_derived_conflicts() {
+        let config: Config = toml::from_str("conflicts = [[\"north-up\", \"east-up\"]]").unwrap();
+        config.validate().unwrap();
+        let conflicts = ConflictMatrix::new(&config);
+        let (north_up, east_up) = (Movement::new(North, Up), Movement::new(East, Up));
+        assert!(conflicts.conflicts(north_up, east_up));
+        assert!(conflicts.conflicts(east_up, north_up));
+        assert!(!conflicts.conflicts(north_up, Movement::new(West, Up)));
+
+        assert!(toml::from_str::<Config>("conflicts = [[\"north-up\", \"east-uturn\"]]").is_err());
+        let same_approach: Config =
+            toml::from_str("conflicts = [[\"north-up\", \"north-left\"]]").unwrap();
+        assert!(same_approach.validate().is_err());
+    }
+
+    #[test]
+    fn zones_are_clear_of_conflicting_vehicles_only() {
+        let conflicts = ConflictMatrix::new(&Config::default());
+        let mut occupancy: ZoneOccupancy = vec![Vec::new(); conflicts.zone_count()];
+        assert!(conflicts.is_clear(Movement::new(North, Up), &occupancy, false));
+
+        let zone = conflicts.zones(Movement::new(South, Up))[0];
+        occupancy[zone].push(Movement::new(South, Up));
+        assert!(conflicts.is_clear(Movement::new(North, Up), &occupancy, false));
+        assert!(!conflicts.is_clear(Movement::new(East, Up), &occupancy, false));
+        // A permissive left turn does not wait for the opposing vehicles, only for the others
+        assert!(!conflicts.is_clear(Movement::new(North, Left), &occupancy, false));
+        assert!(conflicts.is_clear(Movement::new(North, Left), &occupancy, true));
+        assert!(!conflicts.is_clear(Movement::new(West, Left), &occupancy, true));
+    }
+}
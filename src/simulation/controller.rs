@@ -0,0 +1,676 @@
+use crate::config::{Config, ControllerKind, Direction, LeftTurnPhasing};
+use crate::simulation::{
+    ApproachMap, ConflictMatrix, Movement, MovementMap, PedestrianSignal, TrafficLight,
+    VehicleSpawn, ZoneOccupancy,
+};
+
+/// Read-only view of the intersection given to the controllers at every step
+#[derive(Debug, Clone)]
+pub struct Observation<'a> {
+    /// Simulated time in seconds
+    pub time: f64,
+    pub dt: f32,
+    /// Vehicles waiting behind the stop line of each approach
+    pub queues: ApproachMap<usize>,
+    /// Same vehicles, counted by the movement they are going to do
+    pub movement_queues: MovementMap<usize>,
+    /// Movements of the same vehicles, lane by lane from the median, each from the stop line
+    /// backwards
+    pub waiting: ApproachMap<Vec<Vec<Movement>>>,
+    /// Vehicles inside the junction box
+    pub junction_occupancy: usize,
+    /// Movements of the vehicles inside each conflict zone
+    pub zones: ZoneOccupancy,
+    pub conflicts: &'a ConflictMatrix,
+    pub lights: &'a ApproachMap<TrafficLight>,
+    /// Pedestrian signal of the crosswalk across each leg
+    pub crosswalks: &'a ApproachMap<PedestrianSignal>,
+    /// Pedestrians waiting at the curbs of each crosswalk
+    pub pedestrians_waiting: ApproachMap<usize>,
+}
+
+impl Observation<'_> {
+    /// The next vehicle to cross the stop line of one of the lanes of its approach is waiting
+    /// for this movement
+    pub fn is_first_waiting(&self, movement: Movement) -> bool {
+        self.waiting[movement.from]
+            .iter()
+            .any(|lane| lane.first() == Some(&movement))
+    }
+
+    /// Approaches currently showing green
+    pub fn green_approaches(&self) -> impl Iterator<Item = VehicleSpawn> + '_ {
+        self.lights
+            .iter()
+            .filter(|(_, light)| light.is_green())
+            .map(|(spawn, _)| spawn)
+    }
+}
+
+/// Order given by a controller to a traffic light
+/// Green and Red go through the amber phases, the head reaches them on its own timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightCommand {
+    Green(Movement),
+    /// Green without right of way, the vehicles yield to the opposing approach
+    Permissive(Movement),
+    Red(Movement),
+    /// Take every head of the approach out of its cycle, back in it with the next Green or Red
+    Flashing(VehicleSpawn),
+    Off(VehicleSpawn),
+    /// Allow the walk on the crosswalk of a leg, shown once for every push button call
+    Walk(VehicleSpawn),
+    DontWalk(VehicleSpawn),
+}
+
+/// Policy deciding which movements get the green light
+/// Heads not named in the returned commands keep their state
+/// Any set of movements can be asked for, but a head only turns green once every conflicting
+/// head has been red for `all_red_time` and no conflicting vehicle is left in its zones
+/// Crosswalks and the movements crossing them wait for each other the same way, see
+/// `Movement::conflicts_with_crosswalk`
+/// Permissive heads and the opposing approach they yield to do not wait for each other
+pub trait SignalController {
+    fn name(&self) -> &str;
+    fn update(&mut self, observation: &Observation) -> Vec<LightCommand>;
+    /// The lights were taken over, by an emergency preemption, and are given back
+    /// The next update has to command the heads again rather than keep an ongoing phase
+    fn reset(&mut self) {}
+}
+
+impl ControllerKind {
+    /// Build the controller of this kind, with its timings and phases taken from the scenario
+    pub fn build(self, config: &Config, conflicts: &ConflictMatrix) -> Box<dyn SignalController> {
+        let phases = config
+            .left_turn
+            .phases()
+            .iter()
+            .map(|phase| phase.resolved(conflicts))
+            .collect();
+        match self {
+            ControllerKind::MaxQueue => Box::new(MaxQueueController::new(
+                phases,
+                config.capped_max_queue.then_some(config.max_green_time),
+            )),
+            ControllerKind::RoundRobin => {
+                Box::new(RoundRobinController::new(phases, config.max_green_time))
+            }
+            ControllerKind::FixedTime => {
+                Box::new(FixedTimeController::new(phases, config.green_time))
+            }
+        }
+    }
+}
+
+/// Movements served together by a controller
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Phase {
+    /// Movements with the right of way
+    pub protected: Vec<Movement>,
+    /// Left turns served at the same time, yielding to the opposing approach
+    pub permissive: Vec<Movement>,
+    /// Crosswalks given the walk when called, the turning vehicles leaving through them yield
+    pub crossings: Vec<VehicleSpawn>,
+}
+
+impl Phase {
+    /// Every movement of an approach, protected
+    pub fn approach(spawn: VehicleSpawn) -> Self {
+        Phase {
+            protected: Direction::ALL
+                .map(|turn| Movement::new(spawn, turn))
+                .to_vec(),
+            ..Phase::default()
+        }
+    }
+
+    /// Left arrow of an approach
+    pub fn left_arrow(spawn: VehicleSpawn) -> Self {
+        Phase {
+            protected: vec![Movement::new(spawn, Direction::Left)],
+            ..Phase::default()
+        }
+    }
+
+    /// Two opposing approaches together, their left turns permissive
+    pub fn opposing(spawn: VehicleSpawn) -> Self {
+        let both = [spawn, spawn.opposite()];
+        Phase {
+            protected: both
+                .iter()
+                .flat_map(|&from| {
+                    [Direction::Up, Direction::Right].map(|turn| Movement::new(from, turn))
+                })
+                .collect(),
+            permissive: both
+                .map(|from| Movement::new(from, Direction::Left))
+                .to_vec(),
+            ..Phase::default()
+        }
+    }
+
+    pub fn movements(&self) -> impl Iterator<Item = Movement> + '_ {
+        self.protected.iter().chain(self.permissive.iter()).copied()
+    }
+
+    /// Vehicles waiting for one of the movements of the phase, and not stuck behind a vehicle
+    /// waiting for another movement, plus the pedestrians waiting at its crossings
+    pub fn demand(&self, observation: &Observation) -> usize {
+        let served: Vec<Movement> = self.movements().collect();
+        let vehicles: usize = observation
+            .waiting
+            .values()
+            .flatten()
+            .map(|lane| {
+                lane.iter()
+                    .take_while(|movement| served.contains(movement))
+                    .count()
+            })
+            .sum();
+        let pedestrians: usize = self
+            .crossings
+            .iter()
+            .map(|&leg| observation.pedestrians_waiting[leg])
+            .sum();
+        vehicles + pedestrians
+    }
+
+    /// This phase with its inner conflicts settled, a movement crossing a protected one yields
+    /// to it instead: straight movements are protected first, then right and left turns
+    /// Movements of a phase only cross the opposing approach, the one permissive heads yield to
+    /// Every crosswalk none of its movements conflicts with is served alongside
+    pub fn resolved(&self, conflicts: &ConflictMatrix) -> Self {
+        let mut phase = Phase::default();
+        for turn in [Direction::Up, Direction::Right, Direction::Left] {
+            for &movement in self
+                .protected
+                .iter()
+                .filter(|movement| movement.turn == turn)
+            {
+                if conflicts.compatible(movement, &phase.protected) {
+                    phase.protected.push(movement);
+                } else {
+                    phase.permissive.push(movement);
+                }
+            }
+        }
+        phase.permissive.extend(&self.permissive);
+        phase.crossings = VehicleSpawn::ALL
+            .into_iter()
+            .filter(|&leg| {
+                phase
+                    .movements()
+                    .all(|movement| !movement.conflicts_with_crosswalk(leg))
+            })
+            .collect();
+        phase
+    }
+
+    /// This phase plus the movements accepted by `wanted` that are compatible with all of it,
+    /// its crossings included, picked in a fixed order and served protected
+    pub fn extended(&self, conflicts: &ConflictMatrix, wanted: impl Fn(Movement) -> bool) -> Self {
+        let mut phase = self.clone();
+        for movement in Movement::all() {
+            let served: Vec<Movement> = phase.movements().collect();
+            if !served.contains(&movement)
+                && wanted(movement)
+                && conflicts.compatible(movement, &served)
+                && !phase
+                    .crossings
+                    .iter()
+                    .any(|&leg| movement.conflicts_with_crosswalk(leg))
+            {
+                phase.protected.push(movement);
+            }
+        }
+        phase
+    }
+
+    /// Commands giving green to the movements of this phase only, red to every other one, and
+    /// the walk to its crossings only
+    pub fn commands(&self) -> Vec<LightCommand> {
+        let heads = Movement::all().map(|movement| {
+            if self.protected.contains(&movement) {
+                LightCommand::Green(movement)
+            } else if self.permissive.contains(&movement) {
+                LightCommand::Permissive(movement)
+            } else {
+                LightCommand::Red(movement)
+            }
+        });
+        let crosswalks = VehicleSpawn::ALL.into_iter().map(|leg| {
+            if self.crossings.contains(&leg) {
+                LightCommand::Walk(leg)
+            } else {
+                LightCommand::DontWalk(leg)
+            }
+        });
+        heads.chain(crosswalks).collect()
+    }
+}
+
+impl LeftTurnPhasing {
+    /// Phases served in turn by the controllers
+    pub fn phases(self) -> Vec<Phase> {
+        use VehicleSpawn::*;
+        match self {
+            LeftTurnPhasing::Protected => [North, East, South, West].map(Phase::approach).to_vec(),
+            LeftTurnPhasing::Permissive => vec![Phase::opposing(North), Phase::opposing(East)],
+            LeftTurnPhasing::Leading => [North, East]
+                .into_iter()
+                .flat_map(|spawn| {
+                    [
+                        Phase::left_arrow(spawn),
+                        Phase::left_arrow(spawn.opposite()),
+                        Phase::opposing(spawn),
+                    ]
+                })
+                .collect(),
+            LeftTurnPhasing::Lagging => [North, East]
+                .into_iter()
+                .flat_map(|spawn| {
+                    [
+                        Phase::opposing(spawn),
+                        Phase::left_arrow(spawn),
+                        Phase::left_arrow(spawn.opposite()),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Green to the phase with the most waiting vehicles, held until none of them is left
+/// Of phases with as many waiting vehicles, the first one is picked
+/// With a `max_green_time`, the green also ends once it is reached, and of phases with as many
+/// waiting vehicles the one out of green for the longest is picked
+/// A left turn with an arrow phase only counts for that phase, so the arrow is picked once its
+/// left turns outnumber the vehicles the opposing phase has right of way for
+/// Waiting movements first in their lane and compatible with it run alongside
+#[derive(Debug)]
+pub struct MaxQueueController {
+    phases: Vec<Phase>,
+    // Same phases without the permissive movements another phase protects, to count their
+    // waiting vehicles
+    ranked: Vec<Phase>,
+    max_green_time: Option<f32>,
+    // Phase in green and the time it was picked at
+    active: Option<(usize, f64)>,
+    // Time each phase last ended at, 0 for the ones never served
+    ended: Vec<f64>,
+}
+
+impl MaxQueueController {
+    pub fn new(phases: Vec<Phase>, max_green_time: Option<f32>) -> Self {
+        let ranked = phases
+            .iter()
+            .map(|phase| Phase {
+                permissive: phase
+                    .permissive
+                    .iter()
+                    .filter(|movement| {
+                        !phases
+                            .iter()
+                            .any(|other| other.protected.contains(movement))
+                    })
+                    .copied()
+                    .collect(),
+                ..phase.clone()
+            })
+            .collect();
+        Self {
+            ended: vec![0.0; phases.len()],
+            ranked,
+            phases,
+            max_green_time,
+            active: None,
+        }
+    }
+}
+
+impl SignalController for MaxQueueController {
+    fn name(&self) -> &str {
+        "max-queue"
+    }
+
+    fn reset(&mut self) {
+        self.active = None;
+    }
+
+    fn update(&mut self, observation: &Observation) -> Vec<LightCommand> {
+        if let Some((active, since)) = self.active {
+            let expired = self
+                .max_green_time
+                .is_some_and(|max_green_time| observation.time - since >= max_green_time as f64);
+            if self.ranked[active].demand(observation) > 0 && !expired {
+                return Vec::new();
+            }
+            self.active = None;
+            self.ended[active] = observation.time;
+        }
+        let mut max_phase: Option<usize> = None;
+        let mut max_count = 0;
+
+        // Phases are scanned in a fixed order, so ties are broken the same way on every run
+        for (index, phase) in self.ranked.iter().enumerate() {
+            let count = phase.demand(observation);
+            let longer_wait = self.max_green_time.is_some()
+                && max_phase.is_some_and(|max| self.ended[index] < self.ended[max]);
+            if count > max_count || (count > 0 && count == max_count && longer_wait) {
+                max_count = count;
+                max_phase = Some(index);
+            }
+        }
+
+        // Picked in the same step the previous green ends, so the movements of both phases stay green
+        self.active = max_phase.map(|index| (index, observation.time));
+        match max_phase {
+            Some(index) => self.phases[index]
+                .extended(observation.conflicts, |movement| {
+                    observation.is_first_waiting(movement)
+                })
+                .commands(),
+            None => Phase::default().commands(),
+        }
+    }
+}
+
+/// Serve the phases in turn (North, East, South, West by default), skipping the empty ones
+/// Each green is held until its queues are empty or `max_green_time` is reached
+/// Waiting movements first in their lane and compatible with it run alongside
+#[derive(Debug)]
+pub struct RoundRobinController {
+    phases: Vec<Phase>,
+    max_green_time: f32,
+    active: Option<(usize, f64)>,
+    next: usize,
+}
+
+impl RoundRobinController {
+    pub fn new(phases: Vec<Phase>, max_green_time: f32) -> Self {
+        Self {
+            phases,
+            max_green_time,
+            active: None,
+            next: 0,
+        }
+    }
+}
+
+impl SignalController for RoundRobinController {
+    fn name(&self) -> &str {
+        "round-robin"
+    }
+
+    fn reset(&mut self) {
+        self.active = None;
+    }
+
+    fn update(&mut self, observation: &Observation) -> Vec<LightCommand> {
+        if let Some((active, since)) = self.active {
+            let expired = observation.time - since >= self.max_green_time as f64;
+            if self.phases[active].demand(observation) > 0 && !expired {
+                return Vec::new();
+            }
+            self.active = None;
+        }
+
+        for offset in 0..self.phases.len() {
+            let index = (self.next + offset) % self.phases.len();
+            let phase = &self.phases[index];
+            if phase.demand(observation) > 0 {
+                self.next = index + 1;
+                self.active = Some((index, observation.time));
+                return phase
+                    .extended(observation.conflicts, |movement| {
+                        observation.is_first_waiting(movement)
+                    })
+                    .commands();
+            }
+        }
+        Phase::default().commands()
+    }
+}
+
+/// Give green to every phase in turn for `green_time`, whatever the demand
+/// Every movement compatible with it runs alongside
+/// The green time is counted once the lights are green, the clearance phases come on top
+#[derive(Debug)]
+pub struct FixedTimeController {
+    phases: Vec<Phase>,
+    green_time: f32,
+    active: Option<usize>,
+    next: usize,
+}
+
+impl FixedTimeController {
+    pub fn new(phases: Vec<Phase>, green_time: f32) -> Self {
+        Self {
+            phases,
+            green_time,
+            active: None,
+            next: 0,
+        }
+    }
+}
+
+impl SignalController for FixedTimeController {
+    fn name(&self) -> &str {
+        "fixed-time"
+    }
+
+    // The phase cut short by the preemption is served again
+    fn reset(&mut self) {
+        if let Some(active) = self.active.take() {
+            self.next = active;
+        }
+    }
+
+    fn update(&mut self, observation: &Observation) -> Vec<LightCommand> {
+        if let Some(active) = self.active
+            && !self.phases[active].movements().all(|movement| {
+                let head = observation.lights[movement.from].head(movement.turn);
+                head.is_green() && head.elapsed() >= self.green_time
+            })
+        {
+            return Vec::new();
+        }
+
+        let phase = &self.phases[self.next];
+        self.active = Some(self.next);
+        self.next = (self.next + 1) % self.phases.len();
+        phase.extended(observation.conflicts, |_| true).commands()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SignalState;
+    use Direction::{Left, Right, Up};
+    use VehicleSpawn::{East, North, South, West};
+
+    // Parts of the intersection the observations borrow
+    struct Scene {
+        conflicts: ConflictMatrix,
+        lights: ApproachMap<TrafficLight>,
+        crosswalks: ApproachMap<PedestrianSignal>,
+    }
+
+    impl Scene {
+        fn new(config: &Config) -> Self {
+            Scene {
+                conflicts: ConflictMatrix::new(config),
+                lights: ApproachMap::default(),
+                crosswalks: ApproachMap::default(),
+            }
+        }
+
+        fn phases(&self, left_turn: LeftTurnPhasing) -> Vec<Phase> {
+            left_turn
+                .phases()
+                .iter()
+                .map(|phase| phase.resolved(&self.conflicts))
+                .collect()
+        }
+
+        // Vehicles queued in a single lane on the approaches given, from the stop line backwards
+        fn observe(&self, time: f64, waiting: &[(VehicleSpawn, &[Direction])]) -> Observation<'_> {
+            let mut lanes: ApproachMap<Vec<Vec<Movement>>> = ApproachMap::from_fn(|_| vec![vec![]]);
+            let mut movement_queues = MovementMap::default();
+            for &(from, turns) in waiting {
+                for &turn in turns {
+                    let movement = Movement::new(from, turn);
+                    lanes[from][0].push(movement);
+                    movement_queues[movement] += 1;
+                }
+            }
+            Observation {
+                time,
+                dt: 0.1,
+                queues: ApproachMap::from_fn(|spawn| lanes[spawn][0].len()),
+                movement_queues,
+                waiting: lanes,
+                junction_occupancy: 0,
+                zones: vec![Vec::new(); self.conflicts.zone_count()],
+                conflicts: &self.conflicts,
+                lights: &self.lights,
+                crosswalks: &self.crosswalks,
+                pedestrians_waiting: ApproachMap::default(),
+            }
+        }
+
+        // Every head of the approach green for `elapsed` seconds, every other one red
+        fn green_for(&mut self, spawn: VehicleSpawn, elapsed: f32) {
+            for (side, light) in self.lights.iter_mut() {
+                for turn in Direction::ALL {
+                    let head = light.head_mut(turn);
+                    if side == spawn {
+                        head.state = SignalState::Green;
+                        head.timer = elapsed;
+                    } else {
+                        head.state = SignalState::Red;
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_green(commands: &[LightCommand], from: VehicleSpawn, turn: Direction) -> bool {
+        commands.contains(&LightCommand::Green(Movement::new(from, turn)))
+    }
+
+    #[test]
+    fn max_queue_holds_the_longest_queue_until_it_is_empty() {
+        let config = Config::default();
+        let scene = Scene::new(&config);
+        let mut controller =
+            MaxQueueController::new(scene.phases(LeftTurnPhasing::Protected), None);
+
+        let commands = controller.update(&scene.observe(0.0, &[(North, &[Up]), (East, &[Up, Up])]));
+        assert!(is_green(&commands, East, Up));
+        assert!(commands.contains(&LightCommand::Red(Movement::new(North, Up))));
+        // Long past any max green, the queue is not empty yet
+        let waiting: &[(VehicleSpawn, &[Direction])] = &[(North, &[Up, Up, Up]), (East, &[Up])];
+        assert!(controller.update(&scene.observe(100.0, waiting)).is_empty());
+        let commands = controller.update(&scene.observe(101.0, &[(North, &[Up])]));
+        assert!(is_green(&commands, North, Up));
+        assert!(!is_green(&commands, East, Up));
+
+        // Of as long queues, the first phase is picked
+        let mut controller =
+            MaxQueueController::new(scene.phases(LeftTurnPhasing::Protected), None);
+        let commands = controller.update(&scene.observe(0.0, &[(West, &[Up]), (East, &[Up])]));
+        assert!(is_green(&commands, East, Up));
+    }
+
+    #[test]
+    fn capped_max_queue_ends_the_green_and_serves_the_longest_waiting_phase() {
+        let config = Config::default();
+        let scene = Scene::new(&config);
+        let phases = scene.phases(LeftTurnPhasing::Protected);
+        let mut controller = MaxQueueController::new(phases, Some(10.0));
+
+        let commands = controller.update(&scene.observe(0.0, &[(North, &[Up]), (East, &[Up, Up])]));
+        assert!(is_green(&commands, East, Up));
+        assert!(
+            controller
+                .update(&scene.observe(9.9, &[(North, &[Up]), (East, &[Up, Up])]))
+                .is_empty()
+        );
+        // As many vehicles on both, North waited the longest
+        let commands = controller.update(&scene.observe(10.0, &[(North, &[Up]), (East, &[Up])]));
+        assert!(is_green(&commands, North, Up));
+        assert!(!is_green(&commands, East, Up));
+    }
+
+    #[test]
+    fn max_queue_picks_the_left_arrow_for_its_waiting_left_turns() {
+        let config = Config::default();
+        let scene = Scene::new(&config);
+        let phases = scene.phases(LeftTurnPhasing::Leading);
+
+        // Left turns first in the lane, only the arrow lets them go
+        let mut controller = MaxQueueController::new(phases.clone(), None);
+        let commands = controller.update(&scene.observe(0.0, &[(North, &[Left, Left, Up])]));
+        assert!(is_green(&commands, North, Left));
+        assert!(!is_green(&commands, North, Up));
+
+        // Straight ahead first, the opposing phase serves more vehicles than the arrow
+        let mut controller = MaxQueueController::new(phases, None);
+        let waiting: &[(VehicleSpawn, &[Direction])] = &[(North, &[Up, Up, Left]), (South, &[Up])];
+        let commands = controller.update(&scene.observe(0.0, waiting));
+        assert!(is_green(&commands, North, Up));
+        assert!(is_green(&commands, South, Up));
+        assert!(commands.contains(&LightCommand::Permissive(Movement::new(North, Left))));
+    }
+
+    #[test]
+    fn round_robin_serves_the_phases_in_turn_skipping_the_empty_ones() {
+        let config = Config::default();
+        let scene = Scene::new(&config);
+        let phases = scene.phases(LeftTurnPhasing::Protected);
+        let mut controller = RoundRobinController::new(phases, 10.0);
+
+        let all: &[(VehicleSpawn, &[Direction])] =
+            &[(North, &[Up]), (South, &[Right]), (West, &[Up])];
+        assert!(is_green(
+            &controller.update(&scene.observe(0.0, all)),
+            North,
+            Up
+        ));
+        assert!(controller.update(&scene.observe(1.0, all)).is_empty());
+        // North empty, East has nobody waiting
+        let rest: &[(VehicleSpawn, &[Direction])] = &[(South, &[Right]), (West, &[Up])];
+        assert!(is_green(
+            &controller.update(&scene.observe(2.0, rest)),
+            South,
+            Right
+        ));
+        assert!(controller.update(&scene.observe(11.9, rest)).is_empty());
+        // Still waiting, but the max green is reached
+        let commands = controller.update(&scene.observe(12.0, rest));
+        assert!(is_green(&commands, West, Up));
+        assert!(!is_green(&commands, South, Right));
+    }
+
+    #[test]
+    fn fixed_time_gives_every_phase_its_green_time_whatever_the_demand() {
+        let config = Config::default();
+        let mut scene = Scene::new(&config);
+        let phases = scene.phases(LeftTurnPhasing::Protected);
+        let mut controller = FixedTimeController::new(phases, 5.0);
+
+        let mut time = 0.0;
+        for spawn in [North, East, South, West, North] {
+            let commands = controller.update(&scene.observe(time, &[]));
+            assert!(is_green(&commands, spawn, Up), "{spawn:?} not served");
+            // Not counted before the lights are green
+            assert!(controller.update(&scene.observe(time, &[])).is_empty());
+            scene.green_for(spawn, 4.9);
+            assert!(controller.update(&scene.observe(time, &[])).is_empty());
+            scene.green_for(spawn, 5.0);
+            time += 10.0;
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::config::Config;
+use crate::simulation::{ApproachMap, VehicleSpawn};
+use rand::Rng;
+
+/// Automatic vehicle arrivals on every approach, at the rates of `config.demand` scaled over
+/// time by `config.demand_profile`
+/// Each headway is drawn at the rate of the time the previous vehicle arrived
+#[derive(Debug, Clone)]
+pub struct DemandGenerator {
+    /// Off, only the vehicles and pedestrians spawned by hand arrive, the arrivals at
+    /// `config.pedestrian_demand` are switched with the vehicle ones
+    pub enabled: bool,
+    // Simulated time of the next arrival on each approach, None while there is no demand
+    next: ApproachMap<Option<f64>>,
+}
+
+impl Default for DemandGenerator {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            next: ApproachMap::default(),
+        }
+    }
+}
+
+impl DemandGenerator {
+    /// Switch the arrivals on or off, they start over from the current time when back on
+    /// Returns whether they are on
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.next = ApproachMap::default();
+        self.enabled
+    }
+
+    // Approaches with a vehicle arriving by `time`, once per vehicle
+    pub(crate) fn arrivals(
+        &mut self,
+        time: f64,
+        config: &Config,
+        rng: &mut impl Rng,
+    ) -> Vec<VehicleSpawn> {
+        let mut arrivals = Vec::new();
+        if !self.enabled {
+            return arrivals;
+        }
+        for spawn in VehicleSpawn::ALL {
+            let demand = &config.demand[spawn];
+            loop {
+                let from = match self.next[spawn] {
+                    Some(next) if next > time => break,
+                    Some(next) => {
+                        arrivals.push(spawn);
+                        next
+                    }
+                    None => time,
+                };
+                let rate = demand.rate * config.demand_factor(from);
+                if rate <= 0.0 {
+                    self.next[spawn] = None;
+                    break;
+                }
+                self.next[spawn] = Some(from + demand.headway(rate, rng));
+            }
+        }
+        arrivals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ArrivalProcess, Demand};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Config with the given demand on the north approach only
+    fn north(demand: Demand) -> Config {
+        let mut config = Config::default();
+        config.demand[VehicleSpawn::North] = demand;
+        config
+    }
+
+    // Vehicles arriving on the north approach over `hours`, checked every second
+    fn count_arrivals(config: &Config, hours: u32) -> usize {
+        let mut generator = DemandGenerator::default();
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..hours * 3600)
+            .map(|second| {
+                let arrivals = generator.arrivals(second as f64, config, &mut rng);
+                assert!(arrivals.iter().all(|&spawn| spawn == VehicleSpawn::North));
+                arrivals.len()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn every_arrival_process_gives_the_hourly_rate() {
+        for arrivals in [
+            ArrivalProcess::Poisson,
+            ArrivalProcess::ShiftedExponential,
+            ArrivalProcess::Constant,
+        ] {
+            let config = north(Demand {
+                rate: 1200.0,
+                arrivals,
+                min_headway: 1.5,
+            });
+            let count = count_arrivals(&config, 10) as f32;
+            assert!(
+                (count / 10.0 - 1200.0).abs() <= 1200.0 * 0.03,
+                "{arrivals:?}: {count} vehicles in 10 hours"
+            );
+        }
+    }
+
+    #[test]
+    fn shifted_exponential_headways_keep_the_min_headway_and_the_mean() {
+        let demand = Demand {
+            rate: 900.0,
+            arrivals: ArrivalProcess::ShiftedExponential,
+            min_headway: 2.0,
+        };
+        let mut rng = StdRng::seed_from_u64(5);
+        let headways: Vec<f64> = (0..10_000)
+            .map(|_| demand.headway(demand.rate, &mut rng))
+            .collect();
+        assert!(headways.iter().all(|&headway| headway >= 2.0));
+        let mean = headways.iter().sum::<f64>() / headways.len() as f64;
+        assert!((mean - 4.0).abs() < 0.1, "mean headway {mean}");
+        // Constant headways at the mean
+        let constant = Demand {
+            arrivals: ArrivalProcess::Constant,
+            ..demand
+        };
+        assert_eq!(constant.headway(900.0, &mut rng), 4.0);
+    }
+
+    #[test]
+    fn demand_profile_is_interpolated_and_held_past_both_ends() {
+        let mut config = Config::default();
+        assert_eq!(config.demand_factor(50.0), 1.0);
+        config.demand_profile = vec![(100.0, 0.5), (200.0, 1.5), (300.0, 0.0)];
+        assert_eq!(config.demand_factor(0.0), 0.5);
+        assert_eq!(config.demand_factor(100.0), 0.5);
+        assert_eq!(config.demand_factor(150.0), 1.0);
+        assert_eq!(config.demand_factor(200.0), 1.5);
+        assert_eq!(config.demand_factor(250.0), 0.75);
+        assert_eq!(config.demand_factor(1000.0), 0.0);
+
+        // No arrival while the factor is 0
+        let mut config = north(Demand {
+            rate: 3600.0,
+            arrivals: ArrivalProcess::Constant,
+            ..Demand::default()
+        });
+        config.demand_profile = vec![(0.0, 0.0)];
+        assert_eq!(count_arrivals(&config, 1), 0);
+    }
+}
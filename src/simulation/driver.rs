@@ -0,0 +1,64 @@
+use crate::config::{ClassSpec, Config};
+
+// Exponent of the free road term, how sharply a driver stops accelerating near its desired speed
+const ACCELERATION_EXPONENT: i32 = 4;
+
+/// Something the driver must not run into: a vehicle ahead or a stop line to stop at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+    /// Bumper to bumper distance, in pixels
+    pub gap: f32,
+    pub speed: f32,
+    /// Gap to keep once stopped behind it
+    pub minimum_gap: f32,
+}
+
+/// Longitudinal behaviour of a driver, following the Intelligent Driver Model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Driver {
+    pub desired_speed: f32,
+    pub max_acceleration: f32,
+    pub comfortable_deceleration: f32,
+    pub time_headway: f32,
+    pub minimum_gap: f32,
+    pub startup_delay: f32,
+}
+
+impl Driver {
+    /// Driver of a vehicle of the given class, its speed and acceleration are the class ones
+    pub fn new(spec: &ClassSpec, config: &Config) -> Self {
+        Driver {
+            desired_speed: spec.max_speed,
+            max_acceleration: spec.max_acceleration,
+            comfortable_deceleration: config.comfortable_deceleration,
+            time_headway: config.time_headway,
+            minimum_gap: config.safe_distance as f32,
+            startup_delay: config.startup_delay,
+        }
+    }
+
+    /// Acceleration wanted at `speed`, towards the desired speed or behind the obstacle
+    pub fn acceleration(&self, speed: f32, obstacle: Option<Obstacle>) -> f32 {
+        let free = 1.0 - (speed / self.desired_speed).powi(ACCELERATION_EXPONENT);
+        let Some(obstacle) = obstacle else {
+            return self.max_acceleration * free;
+        };
+        if obstacle.gap <= 0.0 {
+            return f32::NEG_INFINITY;
+        }
+        // Gap the driver wants, growing with the speed and with how fast the gap closes
+        let closing = speed - obstacle.speed;
+        let desired_gap = obstacle.minimum_gap
+            + (speed * self.time_headway
+                + speed * closing
+                    / (2.0 * (self.max_acceleration * self.comfortable_deceleration).sqrt()))
+            .max(0.0);
+        self.max_acceleration * (free - (desired_gap / obstacle.gap).powi(2))
+    }
+
+    /// Shortest time to drive `distance` pixels starting at `speed`, at full acceleration
+    pub fn time_to_cover(&self, speed: f32, distance: f32) -> f32 {
+        let a = self.max_acceleration;
+        ((speed * speed + 2.0 * a * distance.max(0.0)).sqrt() - speed) / a
+    }
+}
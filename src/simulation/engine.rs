@@ -0,0 +1,910 @@
+use crate::config::{Config, Direction, VehicleClass};
+use crate::simulation::lane_change::{LaneDecision, decide};
+use crate::simulation::{
+    ApproachMap, CollisionDetector, ConflictMatrix, Crash, DemandGenerator, EventKind, EventLog,
+    LightCommand, Metrics, Movement, MovementMap, Observation, Pedestrian, PedestrianSignal,
+    PedestrianState, Phase, SignalController, SignalHead, SimulationClock, SimulationEvent,
+    StepContext, TrafficLanes, TrafficLight, Vehicle, VehicleLane, VehicleSpawn, VehicleState,
+    ZoneOccupancy,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Whole state of the intersection, independent from any rendering.
+pub struct Simulation {
+    pub config: Config,
+    pub lanes: TrafficLanes,
+    pub lights: ApproachMap<TrafficLight>,
+    /// Pedestrian signal of the crosswalk across each leg
+    pub crosswalks: ApproachMap<PedestrianSignal>,
+    pub pedestrians: Vec<Pedestrian>,
+    // To assign unique IDs to pedestrians
+    pedestrians_spawned: i32,
+    // Vehicles out of the junction for every movement since the start, the ones with a turn
+    // asked for left out
+    realised_movements: MovementMap<u32>,
+    // Time spent in the backlogs by the vehicles spawned since the start, summed, in seconds
+    entry_delay: f64,
+    /// Movements that cannot be green together, from `config.conflicts` or the geometry
+    pub conflicts: ConflictMatrix,
+    /// Policy driving the lights, built from `config.controller`
+    pub controller: Box<dyn SignalController>,
+    pub clock: SimulationClock,
+    /// Every random draw of the simulation goes through this RNG
+    pub rng: StdRng,
+    pub seed: u64,
+    /// Spawns and vehicle state changes, kept until drained
+    pub events: EventLog,
+    /// Emergency vehicle the lights are currently taken over for
+    pub preemption: Option<Preemption>,
+    /// Vehicles that ran into each other, checked after every move
+    pub collisions: CollisionDetector,
+    /// Vehicles arriving on their own, from `config.demand`
+    pub demand: DemandGenerator,
+    /// Trip of every vehicle and figures of every approach, over `config.metrics_interval`
+    pub metrics: Metrics,
+}
+
+/// Lights taken over for an emergency vehicle: its approach gets the green, every other one red
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preemption {
+    /// Id of the emergency vehicle
+    pub id: i32,
+    pub approach: VehicleSpawn,
+    /// Simulated time it started at, in seconds
+    pub since: f64,
+    /// Movements of the other approaches that were green when it started, their green cut short
+    pub cut: Vec<Movement>,
+    /// Time spent at a standstill since then by the vehicles of the movements cut short, summed
+    /// over them, in seconds. The vehicles of the movements that were red anyway would have
+    /// waited all the same, they are left out
+    pub delay: f64,
+}
+
+impl Simulation {
+    /// Simulation of the given scenario, advancing by a fixed step of `config.dt` seconds.
+    /// Two simulations with the same seed and the same inputs behave identically.
+    pub fn new(config: Config, seed: u64) -> Self {
+        let conflicts = ConflictMatrix::new(&config);
+        Simulation {
+            controller: config.controller.build(&config, &conflicts),
+            clock: SimulationClock::new(config.dt),
+            lanes: TrafficLanes::new(&config),
+            metrics: Metrics::new(config.metrics_interval),
+            conflicts,
+            config,
+            lights: ApproachMap::from_fn(|_| TrafficLight::new()),
+            crosswalks: ApproachMap::default(),
+            pedestrians: Vec::new(),
+            pedestrians_spawned: 0,
+            realised_movements: MovementMap::default(),
+            entry_delay: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            events: EventLog::default(),
+            preemption: None,
+            collisions: CollisionDetector::default(),
+            demand: DemandGenerator::default(),
+        }
+    }
+
+    /// Spawn a vehicle on the given side, its class drawn from the class mix of that side and
+    /// its turn from the turning ratios when not given.
+    /// Returns its id, or None when it waits in the backlog of the side for room at the spawn
+    /// point.
+    pub fn spawn_vehicle(&mut self, spawn: VehicleSpawn, turn: Option<Direction>) -> Option<i32> {
+        let class = self.config.class_mix[spawn].sample(&mut self.rng);
+        self.spawn_vehicle_of_class(spawn, class, turn)
+    }
+
+    /// Spawn a vehicle of the given class on the given side, such as an ambulance or a fire
+    /// truck, its turn drawn from the turning ratios when not given.
+    /// Returns its id, or None when it waits in the backlog of the side for room at the spawn
+    /// point.
+    pub fn spawn_vehicle_of_class(
+        &mut self,
+        spawn: VehicleSpawn,
+        class: VehicleClass,
+        turn: Option<Direction>,
+    ) -> Option<i32> {
+        let id =
+            self.lanes
+                .spawn_vehicle_of_class(spawn, class, turn, &self.config, &mut self.rng)?;
+        self.record_spawn(spawn, id);
+        Some(id)
+    }
+
+    fn record_spawn(&mut self, spawn: VehicleSpawn, id: i32) {
+        for lane in &self.lanes.lanes[spawn] {
+            let lane = lane.lock().unwrap();
+            if let Some(vehicle) = lane.iter().find(|vehicle| vehicle.id == id) {
+                self.entry_delay += vehicle.entry_delay as f64;
+                self.metrics.spawned(vehicle, self.clock.time());
+                self.events.push(EventKind::VehicleSpawned {
+                    id,
+                    movement: vehicle.movement(),
+                    class: vehicle.class,
+                    entry_delay: vehicle.entry_delay,
+                });
+                return;
+            }
+        }
+    }
+
+    /// Number of vehicles out of the junction for every movement since the start, their turn
+    /// drawn from the turning ratios. Counted with the turn they really took, after a reroute
+    /// if any, the vehicles still in a backlog or not out yet left out.
+    pub fn realised_movements(&self) -> &MovementMap<u32> {
+        &self.realised_movements
+    }
+
+    /// Number of vehicles waiting in the backlog of the given side.
+    pub fn backlog_length(&self, spawn: VehicleSpawn) -> usize {
+        self.lanes.backlog[spawn].lock().unwrap().len()
+    }
+
+    /// Mean time the vehicles spawned since the start waited in the backlogs before entering
+    /// the road, in seconds.
+    pub fn mean_entry_delay(&self) -> f64 {
+        match self.lanes.spawned_count() {
+            0 => 0.0,
+            spawned => self.entry_delay / spawned as f64,
+        }
+    }
+
+    /// Spawn a vehicle on a random side, returns the chosen side.
+    pub fn spawn_random_vehicle(&mut self) -> VehicleSpawn {
+        let spawn = self.random_spawn();
+        self.spawn_vehicle(spawn, None);
+        spawn
+    }
+
+    /// Side drawn from the simulation RNG.
+    pub fn random_spawn(&mut self) -> VehicleSpawn {
+        VehicleSpawn::ALL[self.rng.random_range(0..4)]
+    }
+
+    /// Bring a pedestrian to a random curb of the crosswalk across the given leg, where it
+    /// presses the push button. Returns its id.
+    pub fn spawn_pedestrian(&mut self, crosswalk: VehicleSpawn) -> i32 {
+        self.pedestrians_spawned += 1;
+        let id = self.pedestrians_spawned;
+        let reversed = self.rng.random_bool(0.5);
+        self.pedestrians
+            .push(Pedestrian::new(id, crosswalk, reversed));
+        self.events
+            .push(EventKind::PedestrianSpawned { id, crosswalk });
+        id
+    }
+
+    /// Number of pedestrians brought to the crosswalks since the start.
+    pub fn pedestrians_spawned(&self) -> i32 {
+        self.pedestrians_spawned
+    }
+
+    /// Number of pedestrians waiting at the curbs of each crosswalk.
+    pub fn pedestrians_waiting(&self) -> ApproachMap<usize> {
+        pedestrians_waiting(&self.pedestrians)
+    }
+
+    /// Advance the whole intersection by one tick: lights first, then vehicles.
+    pub fn step(&mut self) {
+        self.release_backlog();
+        self.spawn_demand();
+        self.spawn_pedestrians();
+        // Vehicles as they are before moving, then once moved
+        let vehicles = self.vehicles();
+        self.update_lights(&vehicles);
+        self.update_crosswalks(&vehicles);
+        self.change_lanes();
+        self.move_vehicles();
+        let mut vehicles = self.vehicles();
+        self.detect_collisions(&mut vehicles);
+        self.move_pedestrians(&vehicles);
+        self.clock.tick();
+        self.events.set_time(self.clock.time());
+        self.metrics
+            .observe(&vehicles, self.clock.dt, self.clock.time());
+    }
+
+    /// Run every fixed step due for the real time elapsed since the last call.
+    pub fn advance(&mut self, elapsed: Duration) {
+        for _ in 0..self.clock.accumulate(elapsed) {
+            self.step();
+        }
+    }
+
+    /// Simulated time in seconds.
+    pub fn time(&self) -> f64 {
+        self.clock.time()
+    }
+
+    /// Snapshot of every vehicle currently on the road.
+    pub fn vehicles(&self) -> Vec<Vehicle> {
+        self.lanes
+            .every_lane()
+            .flat_map(|lane| lane.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Number of vehicles currently on the road.
+    pub fn vehicle_count(&self) -> usize {
+        self.lanes.total_vehicle_count()
+    }
+
+    /// Traffic light of the given side.
+    pub fn light(&self, spawn: VehicleSpawn) -> &TrafficLight {
+        &self.lights[spawn]
+    }
+
+    /// Number of vehicles queued behind the stop line of the given side.
+    pub fn queue_length(&self, spawn: VehicleSpawn) -> usize {
+        self.lanes.queue_length(spawn)
+    }
+
+    /// Number of vehicles inside the junction box.
+    pub fn junction_occupancy(&self) -> usize {
+        self.lanes.junction_occupancy()
+    }
+
+    /// Number of vehicles queued behind the stop line, by the movement they are going to do.
+    pub fn movement_queues(&self) -> MovementMap<usize> {
+        self.lanes.movement_queues()
+    }
+
+    /// Movements of the vehicles queued on the given side, lane by lane from the median, each
+    /// from the stop line backwards.
+    pub fn waiting_movements(&self, spawn: VehicleSpawn) -> Vec<Vec<Movement>> {
+        self.lanes.waiting_movements(spawn)
+    }
+
+    /// Movements of the vehicles inside each conflict zone of the junction box.
+    pub fn zone_occupancy(&self) -> ZoneOccupancy {
+        self.lanes.zone_occupancy(&self.conflicts)
+    }
+
+    /// Every crash since the start, the first contact of two vehicles.
+    pub fn crashes(&self) -> &[Crash] {
+        &self.collisions.crashes
+    }
+
+    /// Take every event recorded since the last call.
+    pub fn drain_events(&mut self) -> Vec<SimulationEvent> {
+        self.events.drain()
+    }
+
+    /// Replace the policy driving the lights, the current light states are kept.
+    pub fn set_controller(&mut self, controller: Box<dyn SignalController>) {
+        self.controller = controller;
+    }
+
+    /// Read-only view of the intersection, as given to the controller.
+    pub fn observation(&self) -> Observation<'_> {
+        observe(
+            &self.clock,
+            &self.lanes,
+            &self.pedestrians,
+            &self.conflicts,
+            &self.lights,
+            &self.crosswalks,
+        )
+    }
+
+    // Start, keep or end the preemption of the lights for an emergency vehicle
+    // The first emergency vehicle calling it keeps it until out of the junction, the next one
+    // gets it then. Returns the approach to give the green to
+    fn update_preemption(&mut self, vehicles: &[Vehicle]) -> Option<VehicleSpawn> {
+        let calling = |id: i32| {
+            vehicles
+                .iter()
+                .any(|vehicle| vehicle.id == id && vehicle.is_calling_preemption(&self.config))
+        };
+
+        if let Some(preemption) = &mut self.preemption {
+            if calling(preemption.id) {
+                let held = vehicles
+                    .iter()
+                    .filter(|vehicle| {
+                        preemption.cut.contains(&vehicle.movement())
+                            && !vehicle.class.is_emergency()
+                            && vehicle.speed == 0.0
+                    })
+                    .count();
+                preemption.delay += held as f64 * self.clock.dt as f64;
+                return Some(preemption.approach);
+            }
+            self.events.push(EventKind::PreemptionEnded {
+                id: preemption.id,
+                approach: preemption.approach,
+                duration: self.clock.time() - preemption.since,
+                delay: preemption.delay,
+            });
+            self.preemption = None;
+            self.controller.reset();
+        }
+
+        // Closest to its stop line first
+        let vehicle = vehicles
+            .iter()
+            .filter(|vehicle| vehicle.is_calling_preemption(&self.config))
+            .max_by(|a, b| a.progress().total_cmp(&b.progress()))?;
+        self.events.push(EventKind::PreemptionStarted {
+            id: vehicle.id,
+            approach: vehicle.spawn,
+        });
+        let cut = Movement::all()
+            .filter(|movement| {
+                movement.from != vehicle.spawn
+                    && self.lights[movement.from].head(movement.turn).is_green()
+            })
+            .collect();
+        self.preemption = Some(Preemption {
+            id: vehicle.id,
+            approach: vehicle.spawn,
+            since: self.clock.time(),
+            cut,
+            delay: 0.0,
+        });
+        Some(vehicle.spawn)
+    }
+
+    // Ask the controller for light commands, apply them and run the light timings
+    // While preempted, the emergency approach gets the green instead, the conflicting heads
+    // going through their clearance first
+    fn update_lights(&mut self, vehicles: &[Vehicle]) {
+        let preempted = self.update_preemption(vehicles);
+        // Built from the fields it reads, so that the controller can be borrowed alongside
+        let observation = observe(
+            &self.clock,
+            &self.lanes,
+            &self.pedestrians,
+            &self.conflicts,
+            &self.lights,
+            &self.crosswalks,
+        );
+        let commands = match preempted {
+            Some(spawn) => Phase::approach(spawn).commands(),
+            None => self.controller.update(&observation),
+        };
+        let zones = observation.zones;
+        for command in commands {
+            match command {
+                LightCommand::Green(movement) => self.head_mut(movement).request_green(),
+                LightCommand::Permissive(movement) => self.head_mut(movement).request_permissive(),
+                LightCommand::Red(movement) => self.head_mut(movement).request_red(),
+                LightCommand::Flashing(spawn) => self.lights[spawn].set_flashing(),
+                LightCommand::Off(spawn) => self.lights[spawn].switch_off(),
+                LightCommand::Walk(leg) => self.crosswalks[leg].request_walk(),
+                LightCommand::DontWalk(leg) => self.crosswalks[leg].request_dont_walk(),
+            }
+        }
+        let all_red_time = self.config.all_red_time;
+
+        // Heads are updated in a fixed order, so of two conflicting heads a single one can
+        // leave the all-red in a step
+        for movement in Movement::all() {
+            // A permissive head and the opposing approach it yields to can run together
+            let yielding = self.lights[movement.from]
+                .head(movement.turn)
+                .wants_permissive();
+            let may_start = self.conflicts.is_clear(movement, &zones, yielding)
+                && Movement::all()
+                    .filter(|&other| self.conflicts.conflicts(movement, other))
+                    .all(|other| {
+                        let head = self.lights[other.from].head(other.turn);
+                        let opposing = other.from == movement.from.opposite();
+                        (opposing && (yielding || head.is_permissive()))
+                            || head.state().is_uncontrolled()
+                            || (head.is_red() && head.elapsed() >= all_red_time)
+                    })
+                && VehicleSpawn::ALL
+                    .into_iter()
+                    .filter(|&leg| movement.conflicts_with_crosswalk(leg))
+                    .all(|leg| self.is_crosswalk_clear(leg));
+            let dt = self.clock.dt;
+            self.lights[movement.from]
+                .head_mut(movement.turn)
+                .update(dt, &self.config, may_start);
+        }
+    }
+
+    // Crosswalks after the heads, so a head and a crosswalk conflicting with it cannot leave
+    // their clearance in the same step
+    fn update_crosswalks(&mut self, vehicles: &[Vehicle]) {
+        let all_red_time = self.config.all_red_time;
+        for leg in VehicleSpawn::ALL {
+            let may_start = Movement::all()
+                .filter(|movement| movement.conflicts_with_crosswalk(leg))
+                .all(|movement| {
+                    let head = self.lights[movement.from].head(movement.turn);
+                    head.is_red() && head.elapsed() >= all_red_time
+                })
+                && !vehicles.iter().any(|vehicle| {
+                    vehicle.movement().conflicts_with_crosswalk(leg)
+                        && vehicle.is_crossing_junction()
+                });
+            let dt = self.clock.dt;
+            self.crosswalks[leg].update(dt, &self.config, may_start);
+        }
+    }
+
+    // Crosswalk showing don't walk for `all_red_time`, and nobody left on it
+    fn is_crosswalk_clear(&self, leg: VehicleSpawn) -> bool {
+        let signal = &self.crosswalks[leg];
+        signal.is_dont_walk()
+            && signal.elapsed() >= self.config.all_red_time
+            && !self
+                .pedestrians
+                .iter()
+                .any(|pedestrian| pedestrian.crosswalk == leg && pedestrian.is_crossing())
+    }
+
+    // Vehicles of the backlogs entering the road as soon as there is room for them
+    fn release_backlog(&mut self) {
+        for (spawn, id) in self.lanes.release_backlog(self.clock.dt, &self.config) {
+            self.record_spawn(spawn, id);
+        }
+    }
+
+    // Vehicles arriving on every approach at the rates of `config.demand`, an arrival finding
+    // no room at the spawn point waits in the backlog
+    fn spawn_demand(&mut self) {
+        let arrivals = self
+            .demand
+            .arrivals(self.time(), &self.config, &mut self.rng);
+        for spawn in arrivals {
+            self.spawn_vehicle(spawn, None);
+        }
+    }
+
+    // Pedestrians arriving at every crosswalk at `pedestrian_demand` per hour, at random, while
+    // the demand generator is on
+    fn spawn_pedestrians(&mut self) {
+        if !self.demand.enabled {
+            return;
+        }
+        let chance = (self.config.pedestrian_demand * self.clock.dt / 3600.0) as f64;
+        for leg in VehicleSpawn::ALL {
+            if chance > 0.0 && self.rng.random_bool(chance.min(1.0)) {
+                self.spawn_pedestrian(leg);
+            }
+        }
+    }
+
+    // Record the vehicles that ran into each other during this step, and stop them where they
+    // are for `crash_clearance_time`, `vehicles` are taken again once they are stopped
+    // Panics on the first one with `strict_collisions`
+    fn detect_collisions(&mut self, vehicles: &mut Vec<Vehicle>) {
+        let crashes = self.collisions.check(vehicles, self.time());
+        let mut stopped = false;
+        for crash in crashes {
+            let [a, b] = crash.vehicles;
+            if self.config.strict_collisions {
+                panic!(
+                    "vehicles {} ({}) and {} ({}) collided at ({:.0}, {:.0}) after {:.2}s",
+                    a,
+                    crash.movements[0],
+                    b,
+                    crash.movements[1],
+                    crash.position.0,
+                    crash.position.1,
+                    crash.time
+                );
+            }
+            self.events.push(EventKind::VehicleCrashed(crash));
+            let clearance = self.config.crash_clearance_time;
+            if clearance <= 0.0 {
+                continue;
+            }
+            for lane in self.lanes.every_lane() {
+                for vehicle in lane.lock().unwrap().iter_mut() {
+                    if crash.vehicles.contains(&vehicle.id)
+                        && vehicle.state != VehicleState::Crashed
+                    {
+                        vehicle.crash(clearance, &mut self.events);
+                        stopped = true;
+                    }
+                }
+            }
+        }
+        if stopped {
+            *vehicles = self.vehicles();
+        }
+    }
+
+    // Move every pedestrian and drop the ones that reached the other curb
+    fn move_pedestrians(&mut self, vehicles: &[Vehicle]) {
+        for pedestrian in self.pedestrians.iter_mut() {
+            let signal = &mut self.crosswalks[pedestrian.crosswalk];
+            pedestrian.r#move(
+                self.clock.dt,
+                &self.config,
+                signal,
+                vehicles,
+                &mut self.events,
+            );
+        }
+        self.pedestrians
+            .retain(|pedestrian| pedestrian.state != PedestrianState::Crossed);
+    }
+
+    fn head_mut(&mut self, movement: Movement) -> &mut SignalHead {
+        self.lights[movement.from].head_mut(movement.turn)
+    }
+
+    // Vehicles out of the junction are moved to their exit road once every lane moved
+    fn move_vehicles(&mut self) {
+        // Every vehicle moves against the others as they were before the step, taken once into
+        // a single list, lane after lane
+        let mut vehicles = Vec::new();
+        let mut snapshot = |lane: &VehicleLane| {
+            let start = vehicles.len();
+            vehicles.extend(lane.lock().unwrap().iter().cloned());
+            start..vehicles.len()
+        };
+        let approaches: ApproachMap<Vec<Range<usize>>> = ApproachMap::from_fn(|spawn| {
+            self.lanes.lanes[spawn].iter().map(&mut snapshot).collect()
+        });
+        let exits: ApproachMap<Vec<Range<usize>>> =
+            ApproachMap::from_fn(|leg| self.lanes.exits[leg].iter().map(&mut snapshot).collect());
+        let exit_roads = ApproachMap::from_fn(|leg| {
+            exits[leg]
+                .iter()
+                .map(|range| &vehicles[range.clone()])
+                .collect()
+        });
+        let mut step = StepContext {
+            dt: self.clock.dt,
+            config: &self.config,
+            lights: &self.lights,
+            conflicts: &self.conflicts,
+            vehicles: &vehicles,
+            crosswalks: &self.crosswalks,
+            pedestrians: &self.pedestrians,
+            exits: &exit_roads,
+            junction_occupancy: self.junction_occupancy(),
+            entered: Vec::new(),
+        };
+        // In the order of `every_lane`
+        let ranges = approaches.values().chain(exits.values()).flatten();
+        for (lane, range) in self.lanes.every_lane().zip(ranges) {
+            Self::move_lane(lane, &vehicles[range.clone()], &mut step, &mut self.events);
+        }
+        for (movement, drawn_turn) in self.lanes.leave_junction() {
+            if drawn_turn {
+                self.realised_movements[movement] += 1;
+            }
+        }
+    }
+
+    // Lane changes before the stop lines, decided front to back on every approach so that a
+    // driver sees the changes made ahead of it
+    fn change_lanes(&mut self) {
+        for (spawn, lanes) in self.lanes.lanes.iter() {
+            let uses = self.config.lane_uses(spawn);
+            let mut lanes: Vec<_> = lanes.iter().map(|lane| lane.lock().unwrap()).collect();
+            let mut order: Vec<(i32, f32)> = lanes
+                .iter()
+                .flat_map(|lane| lane.iter().map(|vehicle| (vehicle.id, vehicle.distance)))
+                .collect();
+            order.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+            for (id, _) in order {
+                let Some((lane, index)) = lanes.iter().enumerate().find_map(|(lane, vehicles)| {
+                    let index = vehicles.iter().position(|vehicle| vehicle.id == id)?;
+                    Some((lane, index))
+                }) else {
+                    continue;
+                };
+                // Decided on the lanes as they are, they are only borrowed mutably to apply it
+                let view: Vec<&VecDeque<Vehicle>> =
+                    lanes.iter().map(|vehicles| &**vehicles).collect();
+                let decision = decide(&view[lane][index], &view, &uses, &self.config);
+                let vehicle = &mut lanes[lane][index];
+                match decision {
+                    LaneDecision::Keep => vehicle.wants_lane = None,
+                    LaneDecision::Wait(target) => vehicle.wants_lane = Some(target),
+                    LaneDecision::Reroute(turn) => {
+                        vehicle.reroute(turn, &self.config, &mut self.events)
+                    }
+                    LaneDecision::Change(target) => {
+                        let mut vehicle = lanes[lane].remove(index).unwrap();
+                        vehicle.change_lane(target, &self.config, &mut self.events);
+                        // Lanes are kept ordered from the front vehicle to the back one
+                        let at = lanes[target]
+                            .iter()
+                            .position(|other| other.distance < vehicle.distance)
+                            .unwrap_or(lanes[target].len());
+                        lanes[target].insert(at, vehicle);
+                    }
+                }
+            }
+        }
+    }
+
+    // Move every vehicle of a lane and drop the ones that reached their destination
+    // `before` are the vehicles of the lane as they were at the start of the step
+    fn move_lane(
+        lane: &VehicleLane,
+        before: &[Vehicle],
+        step: &mut StepContext,
+        events: &mut EventLog,
+    ) {
+        let mut lane = lane.lock().unwrap();
+        for vehicle in lane.iter_mut() {
+            vehicle.r#move(step, before, events);
+        }
+
+        // Remove vehicles that reached destination
+        lane.retain(|vehicle| vehicle.state != VehicleState::Despawned);
+    }
+}
+
+// View of the intersection given to the controllers, see `Simulation::observation`
+fn observe<'a>(
+    clock: &SimulationClock,
+    lanes: &TrafficLanes,
+    pedestrians: &[Pedestrian],
+    conflicts: &'a ConflictMatrix,
+    lights: &'a ApproachMap<TrafficLight>,
+    crosswalks: &'a ApproachMap<PedestrianSignal>,
+) -> Observation<'a> {
+    Observation {
+        time: clock.time(),
+        dt: clock.dt,
+        queues: ApproachMap::from_fn(|spawn| lanes.queue_length(spawn)),
+        movement_queues: lanes.movement_queues(),
+        waiting: ApproachMap::from_fn(|spawn| lanes.waiting_movements(spawn)),
+        junction_occupancy: lanes.junction_occupancy(),
+        zones: lanes.zone_occupancy(conflicts),
+        conflicts,
+        lights,
+        crosswalks,
+        pedestrians_waiting: pedestrians_waiting(pedestrians),
+    }
+}
+
+// Pedestrians waiting at the curbs of each crosswalk
+fn pedestrians_waiting(pedestrians: &[Pedestrian]) -> ApproachMap<usize> {
+    ApproachMap::from_fn(|leg| {
+        pedestrians
+            .iter()
+            .filter(|pedestrian| {
+                pedestrian.crosswalk == leg && pedestrian.state == PedestrianState::Waiting
+            })
+            .count()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Demand, TurningRatios};
+
+    // Simulated time the first vehicle crossed its stop line at
+    fn first_entry(simulation: &mut Simulation, until: f64) -> Option<f64> {
+        while simulation.time() < until {
+            simulation.step();
+            let entered = simulation.drain_events().into_iter().find(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::VehicleStateChanged {
+                        to: VehicleState::InJunction,
+                        ..
+                    }
+                )
+            });
+            if let Some(event) = entered {
+                return Some(event.time);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn stop_line_entry_does_not_depend_on_the_step_length() {
+        let entry = |dt: f32| {
+            let config = Config {
+                dt,
+                ..Config::default()
+            };
+            let mut simulation = Simulation::new(config, 1);
+            simulation.spawn_vehicle(VehicleSpawn::North, Some(Direction::Up));
+            first_entry(&mut simulation, 30.0).expect("the vehicle never entered the junction")
+        };
+        let reference = entry(1.0 / 120.0);
+        for dt in [1.0 / 60.0, 1.0 / 30.0, 0.05, 0.1] {
+            let time = entry(dt);
+            assert!(
+                (time - reference).abs() <= 0.15,
+                "entered at {time:.2}s with dt = {dt}, {reference:.2}s with dt = 1/120"
+            );
+        }
+    }
+
+    #[test]
+    fn vehicles_pull_up_on_the_line_without_jumping_to_it() {
+        for dt in [1.0 / 60.0, 0.1] {
+            let config = Config {
+                dt,
+                ..Config::default()
+            };
+            let mut simulation = Simulation::new(config, 1);
+            // Crossing approaches, one of them waits on red behind the other
+            for _ in 0..3 {
+                simulation.spawn_vehicle(VehicleSpawn::North, Some(Direction::Up));
+                simulation.spawn_vehicle(VehicleSpawn::East, Some(Direction::Up));
+            }
+            let mut stopped = 0;
+            let mut before = simulation.vehicles();
+            while simulation.time() < 30.0 {
+                simulation.step();
+                let vehicles = simulation.vehicles();
+                // Never further than the speed allows, whatever the light
+                for vehicle in vehicles.iter().filter(|vehicle| vehicle.progress() <= 0.0) {
+                    let Some(last) = before.iter().find(|last| last.id == vehicle.id) else {
+                        continue;
+                    };
+                    let moved = vehicle.distance - last.distance;
+                    assert!(
+                        moved <= last.speed.max(vehicle.speed) * dt + 1e-3,
+                        "vehicle {} moved {moved} px at {} px/s with dt = {dt}",
+                        vehicle.id,
+                        last.speed
+                    );
+                }
+                for event in simulation.drain_events() {
+                    let EventKind::VehicleStateChanged {
+                        id,
+                        to: VehicleState::StoppedAtLine,
+                        ..
+                    } = event.kind
+                    else {
+                        continue;
+                    };
+                    let vehicle = vehicles.iter().find(|vehicle| vehicle.id == id).unwrap();
+                    assert!(
+                        vehicle.progress().abs() < 1e-3,
+                        "vehicle {id} stopped {} px from its line with dt = {dt}",
+                        -vehicle.progress()
+                    );
+                    stopped += 1;
+                }
+                before = vehicles;
+            }
+            assert!(stopped > 0, "no vehicle stopped at its line with dt = {dt}");
+        }
+    }
+
+    #[test]
+    fn preemption_delay_only_counts_the_movements_cut_short() {
+        let mut simulation = Simulation::new(Config::default(), 1);
+        for _ in 0..3 {
+            simulation.spawn_vehicle(VehicleSpawn::North, Some(Direction::Up));
+        }
+        while !simulation.light(VehicleSpawn::North).is_green() {
+            simulation.step();
+        }
+        // Waiting on red whatever happens
+        for _ in 0..3 {
+            simulation.spawn_vehicle(VehicleSpawn::West, Some(Direction::Up));
+        }
+        simulation.spawn_vehicle_of_class(
+            VehicleSpawn::East,
+            VehicleClass::Ambulance,
+            Some(Direction::Up),
+        );
+        while simulation.preemption.is_none() {
+            simulation.step();
+        }
+        let preemption = simulation.preemption.clone().unwrap();
+        assert!(!preemption.cut.is_empty());
+        assert!(
+            preemption
+                .cut
+                .iter()
+                .all(|movement| movement.from == VehicleSpawn::North)
+        );
+        // Only the North vehicles held at their line add to the delay
+        let mut held = 0.0;
+        while let Some(preemption) = &simulation.preemption {
+            let delay = preemption.delay;
+            let stopped = simulation
+                .vehicles()
+                .iter()
+                .filter(|vehicle| vehicle.spawn == VehicleSpawn::North && vehicle.speed == 0.0)
+                .count();
+            simulation.step();
+            if let Some(preemption) = &simulation.preemption {
+                let added = preemption.delay - delay;
+                assert!((added - stopped as f64 * simulation.clock.dt as f64).abs() < 1e-9);
+                held += added;
+            }
+        }
+        assert!(held > 0.0, "no North vehicle was held by the preemption");
+    }
+
+    #[test]
+    fn asked_for_turn_is_kept_and_not_counted_as_drawn() {
+        let mut simulation = Simulation::new(Config::default(), 1);
+        let id = simulation
+            .spawn_vehicle(VehicleSpawn::North, Some(Direction::Left))
+            .unwrap();
+        let vehicle = simulation
+            .vehicles()
+            .into_iter()
+            .find(|vehicle| vehicle.id == id);
+        assert_eq!(
+            vehicle.unwrap().movement(),
+            Movement::new(VehicleSpawn::North, Direction::Left)
+        );
+        simulation.spawn_vehicle(VehicleSpawn::South, None);
+        while simulation.time() < 60.0 {
+            simulation.step();
+        }
+        let realised = simulation.realised_movements();
+        assert_eq!(realised.values().sum::<u32>(), 1);
+        assert!(
+            Direction::ALL
+                .iter()
+                .all(|&turn| realised[Movement::new(VehicleSpawn::North, turn)] == 0)
+        );
+    }
+
+    #[test]
+    fn realised_turns_follow_the_turning_ratios() {
+        let mut config = Config {
+            dt: 0.1,
+            ..Config::default()
+        };
+        config.turning[VehicleSpawn::North] = Some(TurningRatios {
+            left: 0.2,
+            up: 0.8,
+            right: 0.0,
+        });
+        let mut simulation = Simulation::new(config, 3);
+        // Spawned in a row, most of them wait in the backlog before entering
+        for _ in 0..300 {
+            simulation.spawn_vehicle(VehicleSpawn::North, None);
+        }
+        let realised = |simulation: &Simulation, turn| {
+            simulation.realised_movements()[Movement::new(VehicleSpawn::North, turn)]
+        };
+        let total = |simulation: &Simulation| simulation.realised_movements().values().sum::<u32>();
+        while total(&simulation) < 300 && simulation.time() < 3600.0 {
+            simulation.step();
+        }
+        assert_eq!(total(&simulation), 300);
+        let left = realised(&simulation, Direction::Left) as f32 / 300.0;
+        assert!((left - 0.2).abs() < 0.05, "left share {left}");
+        assert_eq!(realised(&simulation, Direction::Right), 0);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_run() {
+        let run = |seed: u64| {
+            let config = Config {
+                dt: 0.05,
+                demand: ApproachMap::from_fn(|_| Demand {
+                    rate: 900.0,
+                    ..Demand::default()
+                }),
+                ..Config::default()
+            };
+            let mut simulation = Simulation::new(config, seed);
+            let mut events = Vec::new();
+            while simulation.time() < 60.0 {
+                simulation.step();
+                events.extend(simulation.drain_events());
+            }
+            (simulation.metrics.trips, events)
+        };
+        let (trips, events) = run(7);
+        assert!(!trips.is_empty());
+        assert_eq!(run(7), (trips.clone(), events));
+        assert_ne!(run(8).0, trips);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config::VehicleClass;
+use crate::simulation::{Crash, Movement, VehicleSpawn, VehicleState};
+
+/// Something that happened during the simulation, stamped with the simulated time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationEvent {
+    pub time: f64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// Entered the road, after `entry_delay` seconds in the backlog of its side
+    VehicleSpawned {
+        id: i32,
+        movement: Movement,
+        class: VehicleClass,
+        entry_delay: f32,
+    },
+    VehicleStateChanged {
+        id: i32,
+        from: VehicleState,
+        to: VehicleState,
+    },
+    /// Moved across to the next lane, lanes are counted from the median
+    LaneChanged {
+        id: i32,
+        from: usize,
+        to: usize,
+    },
+    /// Stuck at its stop line in a lane that does not allow its turn, took another one
+    VehicleRerouted {
+        id: i32,
+        from: Movement,
+        to: Movement,
+    },
+    /// Two vehicles ran into each other
+    VehicleCrashed(Crash),
+    PedestrianSpawned {
+        id: i32,
+        crosswalk: VehicleSpawn,
+    },
+    /// Reached the other curb, after waiting `waited` seconds for the walk signal
+    PedestrianCrossed {
+        id: i32,
+        crosswalk: VehicleSpawn,
+        waited: f32,
+    },
+    /// The lights were taken over for an emergency vehicle
+    PreemptionStarted {
+        id: i32,
+        approach: VehicleSpawn,
+    },
+    /// The emergency vehicle left the junction, the controller is back in charge
+    /// `delay` is the time the vehicles of the movements whose green it cut short spent at a
+    /// standstill meanwhile, summed over them, in seconds
+    PreemptionEnded {
+        id: i32,
+        approach: VehicleSpawn,
+        duration: f64,
+        delay: f64,
+    },
+}
+
+/// Events recorded since the last drain, all stamped with the current simulated time
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    time: f64,
+    events: Vec<SimulationEvent>,
+}
+
+impl EventLog {
+    pub(crate) fn set_time(&mut self, time: f64) {
+        self.time = time;
+    }
+
+    pub(crate) fn push(&mut self, kind: EventKind) {
+        self.events.push(SimulationEvent {
+            time: self.time,
+            kind,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SimulationEvent> {
+        self.events.iter()
+    }
+
+    /// Take every recorded event, leaving the log empty
+    pub fn drain(&mut self) -> Vec<SimulationEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::{Config, Direction, LaneUse};
+use crate::simulation::{Obstacle, Vehicle, VehicleState};
+use std::collections::VecDeque;
+
+// What a driver does about its lane before the stop line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LaneDecision {
+    Keep,
+    // Move across into the given lane now
+    Change(usize),
+    // Has to get into the given lane, waiting for a gap in it
+    Wait(usize),
+    // Stopped at the line in a lane that does not allow its turn, takes this one instead
+    Reroute(Direction),
+}
+
+// Lateral behaviour of a driver, following MOBIL: a lane change must be safe for the vehicle
+// that ends up behind it, and worth it once the gain or loss of the neighbours is weighed in
+// by the politeness
+// A driver in a lane that does not allow its turn changes as soon as it is safe, towards the
+// closest lane allowing it
+// `lanes` are the vehicles of every lane of the approach, from the median to the curb
+pub(crate) fn decide(
+    vehicle: &Vehicle,
+    lanes: &[&VecDeque<Vehicle>],
+    uses: &[LaneUse],
+    config: &Config,
+) -> LaneDecision {
+    // Only before the line, from the middle of a lane, and with room to stop before the line
+    // once moved across
+    let progress = vehicle.progress();
+    if progress > 0.0 || vehicle.lateral != 0.0 || vehicle.state == VehicleState::Crashed {
+        return LaneDecision::Keep;
+    }
+    let room = -progress >= vehicle.braking_distance(config);
+
+    if !uses[vehicle.lane].allows(vehicle.direction) {
+        if vehicle.state == VehicleState::StoppedAtLine {
+            let turn = uses[vehicle.lane]
+                .turns()
+                .min_by_key(|turn| turn.index().abs_diff(vehicle.direction.index()))
+                .unwrap_or(vehicle.direction);
+            return LaneDecision::Reroute(turn);
+        }
+        let target = (0..uses.len())
+            .filter(|&lane| uses[lane].allows(vehicle.direction))
+            .min_by_key(|&lane| lane.abs_diff(vehicle.lane))
+            .map(|lane| {
+                if lane < vehicle.lane {
+                    vehicle.lane - 1
+                } else {
+                    vehicle.lane + 1
+                }
+            });
+        return match target {
+            Some(target) if room && is_safe(vehicle, lanes[target], config) => {
+                LaneDecision::Change(target)
+            }
+            Some(target) => LaneDecision::Wait(target),
+            None => LaneDecision::Keep,
+        };
+    }
+
+    // Discretionary changes on the way to the queue, between lanes allowing the turn
+    if vehicle.state != VehicleState::Approaching || !room {
+        return LaneDecision::Keep;
+    }
+    let (leader, follower) = neighbours(vehicle, lanes[vehicle.lane]);
+    let current = acceleration(vehicle, leader);
+    // The follower gets the leader in front once this vehicle is gone
+    let follower_gain = follower.map_or(0.0, |follower| {
+        acceleration(follower, leader) - acceleration(follower, Some(vehicle))
+    });
+    [vehicle.lane.checked_sub(1), Some(vehicle.lane + 1)]
+        .into_iter()
+        .flatten()
+        .filter(|&lane| lane < uses.len() && uses[lane].allows(vehicle.direction))
+        .filter(|&lane| is_safe(vehicle, lanes[lane], config))
+        .map(|lane| {
+            let (new_leader, new_follower) = neighbours(vehicle, lanes[lane]);
+            let new_follower_loss = new_follower.map_or(0.0, |follower| {
+                acceleration(follower, Some(vehicle)) - acceleration(follower, new_leader)
+            });
+            let gain = acceleration(vehicle, new_leader) - current
+                + config.politeness * (follower_gain + new_follower_loss);
+            (lane, gain)
+        })
+        .filter(|&(_, gain)| gain > config.lane_change_threshold)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(LaneDecision::Keep, |(lane, _)| LaneDecision::Change(lane))
+}
+
+// Neither this vehicle nor the one ending up behind it in `lane` would have to brake harder
+// than `braking_deceleration`
+fn is_safe(vehicle: &Vehicle, lane: &VecDeque<Vehicle>, config: &Config) -> bool {
+    let (leader, follower) = neighbours(vehicle, lane);
+    let limit = -config.braking_deceleration;
+    acceleration(vehicle, leader) >= limit
+        && follower.is_none_or(|follower| acceleration(follower, Some(vehicle)) >= limit)
+}
+
+// Closest vehicles ahead of and behind `vehicle` in `lane`, alongside it counts as behind
+// Lanes of an approach are parallel up to the junction box, arc lengths are compared as is
+fn neighbours<'a>(
+    vehicle: &Vehicle,
+    lane: &'a VecDeque<Vehicle>,
+) -> (Option<&'a Vehicle>, Option<&'a Vehicle>) {
+    let others = || lane.iter().filter(|other| other.id != vehicle.id);
+    let leader = others()
+        .filter(|other| other.distance > vehicle.distance)
+        .min_by(|a, b| a.distance.total_cmp(&b.distance));
+    let follower = others()
+        .filter(|other| other.distance <= vehicle.distance)
+        .max_by(|a, b| a.distance.total_cmp(&b.distance));
+    (leader, follower)
+}
+
+// Acceleration of `follower` behind `leader`, on a free road without one
+fn acceleration(follower: &Vehicle, leader: Option<&Vehicle>) -> f32 {
+    let obstacle = leader.map(|leader| Obstacle {
+        gap: leader.distance - follower.distance - (follower.height + leader.height) as f32 / 2.0,
+        speed: leader.speed,
+        minimum_gap: follower.driver.minimum_gap,
+    });
+    follower.driver.acceleration(follower.speed, obstacle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VehicleClass;
+    use crate::simulation::VehicleSpawn;
+
+    // Three lanes on every approach: left, straight, straight-right
+    fn config() -> Config {
+        Config {
+            lanes: 3,
+            ..Config::default()
+        }
+    }
+
+    // Car on the north approach, `distance` along its path
+    fn car(id: i32, lane: usize, turn: Direction, distance: f32, speed: f32) -> Vehicle {
+        let mut vehicle = Vehicle::new(
+            id,
+            VehicleClass::Car,
+            turn,
+            VehicleSpawn::North,
+            lane,
+            &config(),
+        );
+        vehicle.distance = distance;
+        vehicle.speed = speed;
+        vehicle
+    }
+
+    // Lanes of the approach holding the given vehicles
+    fn lanes(vehicles: &[&Vehicle]) -> Vec<VecDeque<Vehicle>> {
+        let mut lanes = vec![VecDeque::new(); 3];
+        for vehicle in vehicles {
+            lanes[vehicle.lane].push_back((*vehicle).clone());
+        }
+        lanes
+    }
+
+    fn decide_for(vehicle: &Vehicle, others: &[&Vehicle], config: &Config) -> LaneDecision {
+        let mut vehicles = others.to_vec();
+        vehicles.push(vehicle);
+        let lanes = lanes(&vehicles);
+        let lanes: Vec<&VecDeque<Vehicle>> = lanes.iter().collect();
+        decide(
+            vehicle,
+            &lanes,
+            &config.lane_uses(VehicleSpawn::North),
+            config,
+        )
+    }
+
+    #[test]
+    fn slower_leader_is_overtaken_in_a_free_lane_allowing_the_turn() {
+        let config = config();
+        let vehicle = car(0, 1, Direction::Up, 200.0, 150.0);
+        assert_eq!(decide_for(&vehicle, &[], &config), LaneDecision::Keep);
+
+        let leader = car(1, 1, Direction::Up, 350.0, 100.0);
+        assert_eq!(
+            decide_for(&vehicle, &[&leader], &config),
+            LaneDecision::Change(2)
+        );
+        // Lane 0 is free too, but for the left turns only
+        let blocking = car(2, 2, Direction::Up, 350.0, 100.0);
+        assert_eq!(
+            decide_for(&vehicle, &[&leader, &blocking], &config),
+            LaneDecision::Keep
+        );
+        // Not worth it below the threshold
+        let config = Config {
+            lane_change_threshold: 1000.0,
+            ..config
+        };
+        assert_eq!(
+            decide_for(&vehicle, &[&leader], &config),
+            LaneDecision::Keep
+        );
+    }
+
+    #[test]
+    fn politeness_weighs_in_the_loss_of_the_new_follower() {
+        let vehicle = car(0, 1, Direction::Up, 200.0, 150.0);
+        let leader = car(1, 1, Direction::Up, 350.0, 100.0);
+        // Has to brake, though not beyond the safe deceleration
+        let follower = car(2, 2, Direction::Up, 30.0, 150.0);
+        let selfish = Config {
+            politeness: 0.0,
+            ..config()
+        };
+        assert_eq!(
+            decide_for(&vehicle, &[&leader, &follower], &selfish),
+            LaneDecision::Change(2)
+        );
+        let polite = Config {
+            politeness: 2.0,
+            ..config()
+        };
+        assert_eq!(
+            decide_for(&vehicle, &[&leader, &follower], &polite),
+            LaneDecision::Keep
+        );
+    }
+
+    #[test]
+    fn change_is_unsafe_when_the_new_follower_or_leader_is_too_close() {
+        let config = config();
+        let vehicle = car(0, 1, Direction::Up, 200.0, 150.0);
+        let lane = |other: &Vehicle| lanes(&[other]).swap_remove(2);
+        assert!(is_safe(&vehicle, &VecDeque::new(), &config));
+        assert!(is_safe(
+            &vehicle,
+            &lane(&car(1, 2, Direction::Up, 30.0, 150.0)),
+            &config
+        ));
+        assert!(!is_safe(
+            &vehicle,
+            &lane(&car(1, 2, Direction::Up, 60.0, 150.0)),
+            &config
+        ));
+        assert!(!is_safe(
+            &vehicle,
+            &lane(&car(1, 2, Direction::Up, 260.0, 0.0)),
+            &config
+        ));
+
+        // Worth it, but not safe for the follower
+        let leader = car(1, 1, Direction::Up, 350.0, 100.0);
+        let follower = car(2, 2, Direction::Up, 60.0, 150.0);
+        assert_eq!(
+            decide_for(&vehicle, &[&leader, &follower], &config),
+            LaneDecision::Keep
+        );
+    }
+
+    #[test]
+    fn lane_not_allowing_the_turn_is_left_as_soon_as_it_is_safe() {
+        let config = config();
+        // Straight on in the left turn lane
+        let vehicle = car(0, 0, Direction::Up, 100.0, 150.0);
+        assert_eq!(decide_for(&vehicle, &[], &config), LaneDecision::Change(1));
+        let alongside = car(1, 1, Direction::Up, 100.0, 150.0);
+        assert_eq!(
+            decide_for(&vehicle, &[&alongside], &config),
+            LaneDecision::Wait(1)
+        );
+        // Too close to the line to stop once moved across
+        let stop_line = vehicle.path.stop_line();
+        let late = car(0, 0, Direction::Up, stop_line - 10.0, 150.0);
+        assert_eq!(decide_for(&late, &[], &config), LaneDecision::Wait(1));
+        // Turning right from the straight lane, towards the curb
+        let right = car(0, 1, Direction::Right, 100.0, 150.0);
+        assert_eq!(decide_for(&right, &[], &config), LaneDecision::Change(2));
+
+        // Stuck at the line, takes the turn of its lane
+        let mut stopped = car(0, 0, Direction::Up, stop_line, 0.0);
+        stopped.state = VehicleState::StoppedAtLine;
+        assert_eq!(
+            decide_for(&stopped, &[&alongside], &config),
+            LaneDecision::Reroute(Direction::Left)
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::config::VehicleClass;
+use crate::simulation::{ApproachMap, Movement, Vehicle, VehicleSpawn, VehicleState};
+use std::collections::{HashMap, HashSet};
+
+// Share of the delays below the reported percentile
+const DELAY_PERCENTILE: f32 = 0.95;
+
+/// Journey of a single vehicle, from the request of its spawn to its removal
+/// Times are simulated seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripRecord {
+    /// Id of the vehicle
+    pub id: i32,
+    pub movement: Movement,
+    pub class: VehicleClass,
+    /// Asked for, before any wait in the backlog of its side
+    pub requested: f64,
+    /// Put on the road at the spawn point
+    pub entered: f64,
+    /// Time spent at a standstill on the road, and how many times it came to one
+    pub stopped_time: f32,
+    pub stops: u32,
+    /// Crossed its stop line, and left the junction box, None while it did not
+    pub junction_entry: Option<f64>,
+    pub junction_exit: Option<f64>,
+    /// Removed from the road, at its destination or once its wreck was cleared
+    pub finished: Option<f64>,
+    /// Ran into another vehicle, its trip ended with the wreck
+    pub crashed: bool,
+    /// Distance driven along its path, in pixels
+    pub distance: f32,
+    /// Time the same distance takes at the desired speed of the driver
+    pub free_flow_time: f32,
+    // Standstill before the stop line so far, the wait of the vehicle, and its part within the
+    // current reporting period
+    wait: f32,
+    period_wait: f32,
+    was_stopped: bool,
+}
+
+impl TripRecord {
+    fn new(vehicle: &Vehicle, time: f64) -> Self {
+        TripRecord {
+            id: vehicle.id,
+            movement: vehicle.movement(),
+            class: vehicle.class,
+            requested: time - vehicle.entry_delay as f64,
+            entered: time,
+            stopped_time: 0.0,
+            stops: 0,
+            junction_entry: None,
+            junction_exit: None,
+            finished: None,
+            crashed: false,
+            distance: 0.0,
+            free_flow_time: 0.0,
+            wait: 0.0,
+            period_wait: 0.0,
+            was_stopped: false,
+        }
+    }
+
+    /// From the request of its spawn to its removal, the wait in the backlog included
+    pub fn travel_time(&self) -> Option<f32> {
+        self.finished
+            .map(|finished| (finished - self.requested) as f32)
+    }
+
+    /// Travel time lost against the free flow
+    pub fn delay(&self) -> Option<f32> {
+        self.travel_time()
+            .map(|travel| (travel - self.free_flow_time).max(0.0))
+    }
+}
+
+/// Figures of an approach over a reporting period
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApproachMetrics {
+    /// Vehicles that crossed the stop line
+    pub throughput: u32,
+    /// Trips completed, the crashed vehicles left out
+    pub completed: u32,
+    /// Delay of the completed trips, on average and the 95th percentile, in seconds
+    pub mean_delay: f32,
+    pub p95_delay: f32,
+    /// Most vehicles queued behind the stop line at once
+    pub max_queue: usize,
+    /// Longest time a vehicle spent at a standstill before its stop line within the period, in
+    /// seconds
+    pub max_wait: f32,
+}
+
+/// Figures of every approach between two simulated times
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsReport {
+    /// Simulated times the period starts and ends at, in seconds
+    pub start: f64,
+    pub end: f64,
+    pub approaches: ApproachMap<ApproachMetrics>,
+}
+
+impl MetricsReport {
+    /// Vehicles crossing the stop line of the approach, per hour
+    pub fn hourly_throughput(&self, spawn: VehicleSpawn) -> f32 {
+        let hours = (self.end - self.start) / 3600.0;
+        if hours > 0.0 {
+            (self.approaches[spawn].throughput as f64 / hours) as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+// Figures gathered since the start of a period, turned into a report at its end
+#[derive(Debug, Clone, Default)]
+struct Period {
+    start: f64,
+    throughput: ApproachMap<u32>,
+    delays: ApproachMap<Vec<f32>>,
+    max_queue: ApproachMap<usize>,
+    max_wait: ApproachMap<f32>,
+}
+
+impl Period {
+    fn starting(start: f64) -> Self {
+        Period {
+            start,
+            ..Default::default()
+        }
+    }
+
+    fn report(&self, end: f64) -> MetricsReport {
+        MetricsReport {
+            start: self.start,
+            end,
+            approaches: ApproachMap::from_fn(|spawn| {
+                let mut delays = self.delays[spawn].clone();
+                delays.sort_by(f32::total_cmp);
+                let mean_delay = match delays.len() {
+                    0 => 0.0,
+                    count => delays.iter().sum::<f32>() / count as f32,
+                };
+                ApproachMetrics {
+                    throughput: self.throughput[spawn],
+                    completed: delays.len() as u32,
+                    mean_delay,
+                    p95_delay: percentile(&delays, DELAY_PERCENTILE),
+                    max_queue: self.max_queue[spawn],
+                    max_wait: self.max_wait[spawn],
+                }
+            }),
+        }
+    }
+}
+
+// Nearest rank percentile of sorted values, 0 without any
+fn percentile(sorted: &[f32], share: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (share * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Trip of every vehicle and figures of every approach, reported every `interval` seconds
+/// and over the whole run
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Length of a reporting period in seconds, a single report at the end when 0
+    pub interval: f32,
+    // Trips of the vehicles still on the road, by id
+    ongoing: HashMap<i32, TripRecord>,
+    /// Trips of the vehicles removed from the road, in the order they were removed
+    pub trips: Vec<TripRecord>,
+    /// Report of every period over, in order
+    pub reports: Vec<MetricsReport>,
+    period: Period,
+    run: Period,
+}
+
+impl Metrics {
+    /// Collector reporting every `interval` simulated seconds, or only for the whole run when 0
+    pub fn new(interval: f32) -> Self {
+        Metrics {
+            interval,
+            ongoing: HashMap::new(),
+            trips: Vec::new(),
+            reports: Vec::new(),
+            period: Period::default(),
+            run: Period::default(),
+        }
+    }
+
+    // Start the trip of a vehicle put on the road at `time`
+    pub(crate) fn spawned(&mut self, vehicle: &Vehicle, time: f64) {
+        self.ongoing
+            .insert(vehicle.id, TripRecord::new(vehicle, time));
+    }
+
+    // Update the trips with the vehicles on the road after a step of `dt` seconds ending at
+    // `time`, the trips of the vehicles gone are over
+    // A period ends with the step reaching its end, counted in it
+    pub(crate) fn observe(&mut self, vehicles: &[Vehicle], dt: f32, time: f64) {
+        let mut queues: ApproachMap<usize> = ApproachMap::default();
+        for vehicle in vehicles {
+            let Some(trip) = self.ongoing.get_mut(&vehicle.id) else {
+                continue;
+            };
+            let spawn = vehicle.spawn;
+            // Taken again, as a vehicle stuck in the wrong lane takes another turn
+            trip.movement = vehicle.movement();
+            trip.distance = vehicle.distance;
+            trip.free_flow_time = vehicle.distance / vehicle.driver.desired_speed;
+            trip.crashed |= vehicle.state == VehicleState::Crashed;
+            let stopped = vehicle.speed == 0.0;
+            if stopped {
+                trip.stopped_time += dt;
+                if !trip.was_stopped {
+                    trip.stops += 1;
+                }
+            }
+            trip.was_stopped = stopped;
+            if vehicle.state.is_queued() {
+                queues[spawn] += 1;
+            }
+
+            if trip.junction_entry.is_none() {
+                if vehicle.progress() > 0.0 {
+                    trip.junction_entry = Some(time);
+                    for period in [&mut self.period, &mut self.run] {
+                        period.throughput[spawn] += 1;
+                    }
+                } else if stopped {
+                    trip.wait += dt;
+                    trip.period_wait += dt;
+                    let max_wait = &mut self.period.max_wait[spawn];
+                    *max_wait = max_wait.max(trip.period_wait);
+                    let max_wait = &mut self.run.max_wait[spawn];
+                    *max_wait = max_wait.max(trip.wait);
+                }
+            }
+            if trip.junction_exit.is_none() && vehicle.state == VehicleState::Exiting {
+                trip.junction_exit = Some(time);
+            }
+        }
+        for period in [&mut self.period, &mut self.run] {
+            for (spawn, max_queue) in period.max_queue.iter_mut() {
+                *max_queue = (*max_queue).max(queues[spawn]);
+            }
+        }
+
+        // Vehicles no longer on the road reached their destination, or were cleared
+        let present: HashSet<i32> = vehicles.iter().map(|vehicle| vehicle.id).collect();
+        let gone: Vec<i32> = self
+            .ongoing
+            .keys()
+            .filter(|id| !present.contains(id))
+            .copied()
+            .collect();
+        let mut finished: Vec<TripRecord> = gone
+            .into_iter()
+            .filter_map(|id| self.ongoing.remove(&id))
+            .collect();
+        finished.sort_by_key(|trip| trip.id);
+        for mut trip in finished {
+            trip.finished = Some(time);
+            if !trip.crashed
+                && let Some(delay) = trip.delay()
+            {
+                for period in [&mut self.period, &mut self.run] {
+                    period.delays[trip.movement.from].push(delay);
+                }
+            }
+            self.trips.push(trip);
+        }
+
+        if self.interval > 0.0 && time >= self.period.start + self.interval as f64 {
+            self.reports.push(self.period.report(time));
+            self.period = Period::starting(time);
+            for trip in self.ongoing.values_mut() {
+                trip.period_wait = 0.0;
+            }
+        }
+    }
+
+    /// Figures of the whole run up to `time`
+    pub fn summary(&self, time: f64) -> MetricsReport {
+        self.run.report(time)
+    }
+
+    /// Trips of the vehicles still on the road
+    pub fn ongoing(&self) -> impl Iterator<Item = &TripRecord> {
+        self.ongoing.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Direction};
+
+    #[test]
+    fn percentile_takes_the_nearest_rank() {
+        let delays: Vec<f32> = (1..=20).map(|delay| delay as f32).collect();
+        assert_eq!(percentile(&delays, DELAY_PERCENTILE), 19.0);
+        assert_eq!(percentile(&delays[..1], DELAY_PERCENTILE), 1.0);
+        assert_eq!(percentile(&[], DELAY_PERCENTILE), 0.0);
+    }
+
+    #[test]
+    fn step_reaching_the_end_of_a_period_is_counted_in_it() {
+        let north = VehicleSpawn::North;
+        let config = Config::default();
+        let mut vehicle = Vehicle::new(1, VehicleClass::Car, Direction::Up, north, 0, &config);
+        vehicle.speed = 0.0;
+        let mut metrics = Metrics::new(1.0);
+        metrics.spawned(&vehicle, 0.0);
+        // At a standstill before its stop line for 1.75 seconds, across it with the step
+        // ending the second period
+        for step in 1..=7 {
+            metrics.observe(std::slice::from_ref(&vehicle), 0.25, step as f64 * 0.25);
+        }
+        vehicle.distance = vehicle.path.stop_line() + 1.0;
+        vehicle.speed = 10.0;
+        metrics.observe(&[vehicle], 0.25, 2.0);
+
+        let [first, second] = metrics.reports.as_slice() else {
+            panic!("{} reports instead of 2", metrics.reports.len());
+        };
+        assert_eq!((first.start, first.end), (0.0, 1.0));
+        assert_eq!((second.start, second.end), (1.0, 2.0));
+        assert_eq!(first.approaches[north].max_wait, 1.0);
+        assert_eq!(second.approaches[north].max_wait, 0.75);
+        assert_eq!(first.approaches[north].throughput, 0);
+        assert_eq!(second.approaches[north].throughput, 1);
+        let run = metrics.summary(2.0);
+        assert_eq!(run.approaches[north].max_wait, 1.75);
+        assert_eq!(run.approaches[north].throughput, 1);
+    }
+}
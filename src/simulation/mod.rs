@@ -1,9 +1,34 @@
 // Making a module of all the folder's files
+pub mod approach;
+pub mod clock;
+pub mod collision;
+pub mod conflict;
+pub mod controller;
+pub mod demand;
+pub mod driver;
+pub mod engine;
+pub mod events;
+pub(crate) mod lane_change;
+pub mod metrics;
+pub mod path;
+pub mod pedestrian;
 pub mod traffic_lane;
 pub mod traffic_light;
 pub mod vehicle;
 
 // Exporting them
+pub use approach::*;
+pub use clock::*;
+pub use collision::*;
+pub use conflict::*;
+pub use controller::*;
+pub use demand::*;
+pub use driver::*;
+pub use engine::*;
+pub use events::*;
+pub use metrics::*;
+pub use path::*;
+pub use pedestrian::*;
 pub use traffic_lane::*;
 pub use traffic_light::*;
 pub use vehicle::*;
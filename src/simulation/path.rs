@@ -0,0 +1,372 @@
+use crate::config::{Config, Direction};
+use crate::simulation::Movement;
+
+// Handle length of a cubic Bezier approximating a quarter circle, relative to its radius
+const QUARTER_CIRCLE_HANDLE: f32 = 0.552_284_8;
+// Samples used to measure the arc length of a curve
+const CURVE_SAMPLES: usize = 32;
+
+type Point = (f32, f32);
+
+// Piece of a path, straight or curved
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Line(Point, Point),
+    // Cubic Bezier with the arc length reached at every sample, for the arc length lookup
+    Curve([Point; 4], Vec<f32>),
+}
+
+impl Segment {
+    fn curve(points: [Point; 4]) -> Self {
+        let mut lengths = Vec::with_capacity(CURVE_SAMPLES + 1);
+        let mut length = 0.0;
+        let mut previous = points[0];
+        lengths.push(0.0);
+        for i in 1..=CURVE_SAMPLES {
+            let point = bezier(&points, i as f32 / CURVE_SAMPLES as f32);
+            length += distance(previous, point);
+            lengths.push(length);
+            previous = point;
+        }
+        Segment::Curve(points, lengths)
+    }
+
+    fn length(&self) -> f32 {
+        match self {
+            Segment::Line(start, end) => distance(*start, *end),
+            Segment::Curve(_, lengths) => lengths[CURVE_SAMPLES],
+        }
+    }
+
+    // Point and unit heading at `s` pixels from the start of the segment
+    fn at(&self, s: f32) -> (Point, Point) {
+        match self {
+            Segment::Line(start, end) => {
+                let heading = normalize((end.0 - start.0, end.1 - start.1));
+                ((start.0 + heading.0 * s, start.1 + heading.1 * s), heading)
+            }
+            Segment::Curve(points, lengths) => {
+                // Sample before `s`, then linear interpolation of the curve parameter
+                let i = lengths
+                    .partition_point(|&length| length <= s)
+                    .clamp(1, CURVE_SAMPLES);
+                let span = lengths[i] - lengths[i - 1];
+                let ratio = if span > 0.0 {
+                    (s - lengths[i - 1]) / span
+                } else {
+                    0.0
+                };
+                let t = ((i - 1) as f32 + ratio.clamp(0.0, 1.0)) / CURVE_SAMPLES as f32;
+                (bezier(points, t), normalize(bezier_tangent(points, t)))
+            }
+        }
+    }
+}
+
+/// Trajectory of a movement from its spawn point to its destination, by arc length
+/// Turns are quarter circle curves from the edge of the junction box to the exit lane of the
+/// same rank as the approach lane
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    segments: Vec<Segment>,
+    length: f32,
+    // Arc length at the stop line, where the center of a vehicle touching the junction is
+    stop_line: f32,
+    // Arc length where the curve starts, None when going straight
+    turn_start: Option<f32>,
+    // Arc length where the center of a vehicle leaves the junction bounds
+    junction_exit: f32,
+    // Arc lengths where the front of a vehicle reaches the crosswalk of the exit leg, and
+    // where its rear leaves it
+    crosswalk_entry: f32,
+    crosswalk_exit: f32,
+}
+
+impl Path {
+    /// Path of a vehicle of the given length from the given lane, the stop line and the
+    /// junction exit depend on its length
+    /// The paths of every lane of an approach have the same arc length up to the junction box
+    pub fn new(movement: Movement, lane: usize, length: u32, config: &Config) -> Self {
+        let spawn = movement.from;
+        let start = config.spawn_position(spawn, lane);
+        let stop = config.light_position(spawn, lane, length);
+        let end = movement.destination(lane, config);
+        let heading = spawn.vector();
+
+        let mut segments = Vec::new();
+        let mut turn_start = None;
+        match config.turn_offset(movement.turn, lane) {
+            Some(radius) => {
+                // Corner where the approach and exit lanes cross, the curve spans the part of
+                // both lanes inside the junction box
+                let edge = config.box_edge(spawn, lane);
+                let corner = (edge.0 + heading.0 * radius, edge.1 + heading.1 * radius);
+                let exit = match movement.turn {
+                    Direction::Left => (heading.1, -heading.0),
+                    _ => (-heading.1, heading.0),
+                };
+                let curve_start = (corner.0 - heading.0 * radius, corner.1 - heading.1 * radius);
+                let curve_end = (corner.0 + exit.0 * radius, corner.1 + exit.1 * radius);
+                let handle = radius * QUARTER_CIRCLE_HANDLE;
+
+                turn_start = Some(distance(start, curve_start));
+                segments.push(Segment::Line(start, curve_start));
+                segments.push(Segment::curve([
+                    curve_start,
+                    (
+                        curve_start.0 + heading.0 * handle,
+                        curve_start.1 + heading.1 * handle,
+                    ),
+                    (curve_end.0 - exit.0 * handle, curve_end.1 - exit.1 * handle),
+                    curve_end,
+                ]));
+                segments.push(Segment::Line(curve_end, end));
+            }
+            None => segments.push(Segment::Line(start, end)),
+        }
+
+        let mut path = Path {
+            length: segments.iter().map(Segment::length).sum(),
+            segments,
+            stop_line: distance(start, stop),
+            turn_start,
+            junction_exit: 0.0,
+            crosswalk_entry: 0.0,
+            crosswalk_exit: 0.0,
+        };
+
+        // Walked pixel by pixel from where the front enters the box, it is only a few lanes wide
+        let (min_x, min_y, max_x, max_y) = config.junction_bounds(length);
+        let mut s = path.stop_line + config.stop_line_setback as f32;
+        while s < path.length {
+            let ((x, y), _) = path.at(s);
+            if x < min_x || x > max_x || y < min_y || y > max_y {
+                break;
+            }
+            s += 1.0;
+        }
+        path.junction_exit = s;
+
+        // Front and rear of the vehicle, walked from where the front enters the box
+        let half = length as f32 / 2.0;
+        let end_at = |s: f32, side: f32| {
+            let ((x, y), (hx, hy)) = path.at(s);
+            (x + hx * half * side, y + hy * half * side)
+        };
+        let (min_x, min_y, max_x, max_y) = config.junction_box();
+        let inside = |(x, y): (f32, f32), margin: f32| {
+            x > min_x - margin && x < max_x + margin && y > min_y - margin && y < max_y + margin
+        };
+        let mut s = path.stop_line + config.stop_line_setback as f32 + 1.0;
+        while s < path.length && inside(end_at(s, 1.0), 0.0) {
+            s += 1.0;
+        }
+        let entry = s;
+        let crosswalk = config.crosswalk_width as f32;
+        while s < path.length && inside(end_at(s, -1.0), crosswalk) {
+            s += 1.0;
+        }
+        path.crosswalk_entry = entry;
+        path.crosswalk_exit = s;
+        path
+    }
+
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    pub fn stop_line(&self) -> f32 {
+        self.stop_line
+    }
+
+    pub fn turn_start(&self) -> Option<f32> {
+        self.turn_start
+    }
+
+    pub fn junction_exit(&self) -> f32 {
+        self.junction_exit
+    }
+
+    pub fn crosswalk_entry(&self) -> f32 {
+        self.crosswalk_entry
+    }
+
+    pub fn crosswalk_exit(&self) -> f32 {
+        self.crosswalk_exit
+    }
+
+    /// Arc length up to which two paths from the same approach overlap
+    pub fn shared_length(&self, other: &Path) -> f32 {
+        if self.segments == other.segments {
+            return self.length;
+        }
+        match (self.turn_start, other.turn_start) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => self.length.min(other.length),
+        }
+    }
+
+    /// Point and unit heading at arc length `s`, clamped to the ends of the path
+    pub fn at(&self, s: f32) -> ((f32, f32), (f32, f32)) {
+        let mut s = s.clamp(0.0, self.length);
+        for (i, segment) in self.segments.iter().enumerate() {
+            let length = segment.length();
+            if s <= length || i + 1 == self.segments.len() {
+                return segment.at(s);
+            }
+            s -= length;
+        }
+        unreachable!("a path has at least one segment")
+    }
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn normalize(v: Point) -> Point {
+    let length = (v.0 * v.0 + v.1 * v.1).sqrt();
+    if length > 0.0 {
+        (v.0 / length, v.1 / length)
+    } else {
+        v
+    }
+}
+
+fn bezier(points: &[Point; 4], t: f32) -> Point {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    (
+        a * points[0].0 + b * points[1].0 + c * points[2].0 + d * points[3].0,
+        a * points[0].1 + b * points[1].1 + c * points[2].1 + d * points[3].1,
+    )
+}
+
+fn bezier_tangent(points: &[Point; 4], t: f32) -> Point {
+    let u = 1.0 - t;
+    let (a, b, c) = (3.0 * u * u, 6.0 * u * t, 3.0 * t * t);
+    (
+        a * (points[1].0 - points[0].0)
+            + b * (points[2].0 - points[1].0)
+            + c * (points[3].0 - points[2].0),
+        a * (points[1].1 - points[0].1)
+            + b * (points[2].1 - points[1].1)
+            + c * (points[3].1 - points[2].1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Length of the vehicles the paths are built for
+    const LENGTH: u32 = 50;
+
+    fn config() -> Config {
+        Config {
+            lanes: 2,
+            ..Config::default()
+        }
+    }
+
+    // Path of every movement from every lane
+    fn paths(config: &Config) -> impl Iterator<Item = (Movement, usize, Path)> + '_ {
+        Movement::all().flat_map(move |movement| {
+            (0..config.lanes as usize)
+                .map(move |lane| (movement, lane, Path::new(movement, lane, LENGTH, config)))
+        })
+    }
+
+    fn close(a: Point, b: Point, tolerance: f32) -> bool {
+        distance(a, b) <= tolerance
+    }
+
+    #[test]
+    fn length_is_the_sum_of_the_segments() {
+        let config = config();
+        for (movement, lane, path) in paths(&config) {
+            let sum: f32 = path.segments.iter().map(Segment::length).sum();
+            assert_eq!(path.length(), sum, "{movement} lane {lane}");
+            // The curve is measured close to the quarter circle it stands for
+            if let Some(radius) = config.turn_offset(movement.turn, lane) {
+                let curve = path.segments[1].length();
+                let quarter = std::f32::consts::FRAC_PI_2 * radius;
+                assert!(
+                    (curve - quarter).abs() < quarter * 0.01,
+                    "{movement} lane {lane}: curve of {curve} for a quarter circle of {quarter}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn position_and_heading_are_continuous_across_the_joins() {
+        let config = config();
+        for (movement, lane, path) in paths(&config) {
+            let mut join = 0.0;
+            for segment in &path.segments[..path.segments.len() - 1] {
+                join += segment.length();
+                let (before, heading_before) = path.at(join - 0.5);
+                let (after, heading_after) = path.at(join + 0.5);
+                assert!(
+                    close(before, after, 1.01),
+                    "{movement} lane {lane} at {join}"
+                );
+                assert!(
+                    close(heading_before, heading_after, 0.05),
+                    "{movement} lane {lane} at {join}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn turns_leave_the_junction_heading_away_from_it() {
+        let config = config();
+        for (movement, lane, path) in paths(&config) {
+            let (_, start) = path.at(0.0);
+            let (_, end) = path.at(path.length());
+            assert!(close(start, movement.from.vector(), 1e-4));
+            // The exit side vector points into the junction, the vehicle leaves it the other way
+            let (x, y) = movement.exit().vector();
+            assert!(
+                close(end, (-x, -y), 1e-3),
+                "{movement} lane {lane}: {end:?}"
+            );
+            let turned = start.0 * end.1 - start.1 * end.0;
+            match movement.turn {
+                Direction::Up => assert!(close(start, end, 1e-4)),
+                // Screen coordinates, y down: a left turn is counterclockwise on screen
+                Direction::Left => assert!((turned + 1.0).abs() < 1e-3, "{movement}"),
+                Direction::Right => assert!((turned - 1.0).abs() < 1e-3, "{movement}"),
+            }
+        }
+    }
+
+    #[test]
+    fn stop_line_is_the_setback_away_from_the_junction_box() {
+        for setback in [30, 35, 60] {
+            let config = Config {
+                stop_line_setback: setback,
+                ..config()
+            };
+            for (movement, lane, path) in paths(&config) {
+                let (center, _) = path.at(path.stop_line());
+                let (front, _) = path.at(path.stop_line() + LENGTH as f32 / 2.0);
+                let edge = config.box_edge(movement.from, lane);
+                assert!(
+                    (distance(front, edge) - setback as f32).abs() < 1e-3,
+                    "{movement} lane {lane}: front {front:?}, box edge {edge:?}"
+                );
+                assert!(close(
+                    center,
+                    config.light_position(movement.from, lane, LENGTH),
+                    1e-3
+                ));
+                // Still on the straight approach
+                assert!(path.turn_start().is_none_or(|turn| turn > path.stop_line()));
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::config::Config;
+use crate::simulation::{EventKind, EventLog, Footprint, PedestrianSignal, Vehicle, VehicleSpawn};
+
+/// Size of a pedestrian seen from above, in pixels
+pub const PEDESTRIAN_SIZE: f32 = 8.0;
+
+/// Lifecycle of a pedestrian, from its arrival at the curb to the other side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PedestrianState {
+    /// At the curb, the push button pressed
+    Waiting,
+    /// On the crosswalk
+    Crossing,
+    /// Reached the other curb, removed at the end of the step
+    Crossed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pedestrian {
+    pub id: i32,
+    /// Leg whose crosswalk is crossed
+    pub crosswalk: VehicleSpawn,
+    /// Walking from the second end of the crosswalk to the first one
+    pub reversed: bool,
+    pub state: PedestrianState,
+    /// Distance walked from the curb, in pixels
+    pub walked: f32,
+    /// Time spent waiting at the curb, in seconds
+    pub waited: f32,
+}
+
+impl Pedestrian {
+    pub fn new(id: i32, crosswalk: VehicleSpawn, reversed: bool) -> Self {
+        Pedestrian {
+            id,
+            crosswalk,
+            reversed,
+            state: PedestrianState::Waiting,
+            walked: 0.0,
+            waited: 0.0,
+        }
+    }
+
+    pub fn is_crossing(&self) -> bool {
+        self.state == PedestrianState::Crossing
+    }
+
+    /// Center of the pedestrian, along the middle line of the crosswalk
+    pub fn position(&self, config: &Config) -> (f32, f32) {
+        let (mut from, mut to) = config.crosswalk_ends(self.crosswalk);
+        if self.reversed {
+            (from, to) = (to, from);
+        }
+        let t = (self.walked / config.crossing_length()).min(1.0);
+        (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+    }
+
+    /// Square taken by the pedestrian
+    pub fn corners(&self, config: &Config) -> Footprint {
+        let (x, y) = self.position(config);
+        let half = PEDESTRIAN_SIZE / 2.0;
+        [
+            (x - half, y - half),
+            (x + half, y - half),
+            (x + half, y + half),
+            (x - half, y + half),
+        ]
+    }
+
+    // Move the pedestrian by one simulation step of `dt` seconds
+    // A waiting pedestrian keeps the push button pressed, and steps in on walk once no vehicle
+    // is about to drive over the crosswalk
+    pub(crate) fn r#move(
+        &mut self,
+        dt: f32,
+        config: &Config,
+        signal: &mut PedestrianSignal,
+        vehicles: &[Vehicle],
+        events: &mut EventLog,
+    ) {
+        match self.state {
+            PedestrianState::Waiting => {
+                let clear = !vehicles
+                    .iter()
+                    .any(|vehicle| vehicle.is_committed_to_crosswalk(self.crosswalk, config));
+                if signal.is_walk() && clear {
+                    self.state = PedestrianState::Crossing;
+                } else {
+                    signal.call();
+                    self.waited += dt;
+                }
+            }
+            PedestrianState::Crossing => {
+                self.walked += config.pedestrian_speed * dt;
+                if self.walked >= config.crossing_length() {
+                    self.state = PedestrianState::Crossed;
+                    events.push(EventKind::PedestrianCrossed {
+                        id: self.id,
+                        crosswalk: self.crosswalk,
+                        waited: self.waited,
+                    });
+                }
+            }
+            PedestrianState::Crossed => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Direction, VehicleClass};
+    use crate::simulation::{Movement, Simulation};
+    use VehicleSpawn::{North, South};
+
+    // Step length, exact in binary so the waited times add up exactly
+    const DT: f32 = 0.25;
+
+    // Signal showing walk, as the controller and a push button call leave it
+    fn walk(config: &Config) -> PedestrianSignal {
+        let mut signal = PedestrianSignal::default();
+        signal.request_walk();
+        signal.call();
+        signal.update(DT, config, true);
+        assert!(signal.is_walk());
+        signal
+    }
+
+    // Car going straight from North, leaving the junction over the South crosswalk, at the
+    // given distance along its path
+    fn car_at(config: &Config, distance: impl Fn(&Vehicle) -> f32) -> Vehicle {
+        let mut vehicle = Vehicle::new(1, VehicleClass::Car, Direction::Up, North, 0, config);
+        vehicle.distance = distance(&vehicle);
+        vehicle
+    }
+
+    #[test]
+    fn waiting_pedestrian_calls_the_signal_until_walk() {
+        let config = Config::default();
+        let mut signal = PedestrianSignal::default();
+        let mut events = EventLog::default();
+        let mut pedestrian = Pedestrian::new(1, South, false);
+
+        for _ in 0..4 {
+            pedestrian.r#move(DT, &config, &mut signal, &[], &mut events);
+        }
+        assert_eq!(pedestrian.state, PedestrianState::Waiting);
+        assert!(signal.is_called());
+        assert_eq!(pedestrian.waited, 4.0 * DT);
+        assert_eq!(pedestrian.walked, 0.0);
+
+        let mut signal = walk(&config);
+        pedestrian.r#move(DT, &config, &mut signal, &[], &mut events);
+        assert_eq!(pedestrian.state, PedestrianState::Crossing);
+        assert_eq!(pedestrian.waited, 4.0 * DT);
+    }
+
+    #[test]
+    fn pedestrian_does_not_step_in_before_a_committed_vehicle() {
+        let config = Config::default();
+        let mut signal = walk(&config);
+        let mut events = EventLog::default();
+        let mut pedestrian = Pedestrian::new(1, South, false);
+
+        // Inside the junction, about to drive over the crosswalk
+        let inside = car_at(&config, |vehicle| vehicle.path.stop_line() + 1.0);
+        assert!(inside.is_committed_to_crosswalk(South, &config));
+        pedestrian.r#move(DT, &config, &mut signal, &[inside], &mut events);
+        assert_eq!(pedestrian.state, PedestrianState::Waiting);
+        assert_eq!(pedestrian.waited, DT);
+
+        // Past the crosswalk, or leaving the junction over another one
+        let past = car_at(&config, |vehicle| vehicle.path.crosswalk_exit() + 1.0);
+        let mut other = Pedestrian::new(2, North, false);
+        let inside = car_at(&config, |vehicle| vehicle.path.stop_line() + 1.0);
+        other.r#move(DT, &config, &mut signal, &[inside], &mut events);
+        assert_eq!(other.state, PedestrianState::Crossing);
+        pedestrian.r#move(DT, &config, &mut signal, &[past], &mut events);
+        assert_eq!(pedestrian.state, PedestrianState::Crossing);
+    }
+
+    #[test]
+    fn pedestrian_crosses_in_the_crossing_length_and_reports_its_wait() {
+        let config = Config::default();
+        let mut events = EventLog::default();
+        let mut pedestrian = Pedestrian::new(3, North, true);
+
+        let mut signal = PedestrianSignal::default();
+        for _ in 0..6 {
+            pedestrian.r#move(DT, &config, &mut signal, &[], &mut events);
+        }
+        let mut signal = walk(&config);
+        let mut steps = 0;
+        while pedestrian.state != PedestrianState::Crossed {
+            pedestrian.r#move(DT, &config, &mut signal, &[], &mut events);
+            steps += 1;
+            if pedestrian.state == PedestrianState::Crossing {
+                assert!(pedestrian.walked < config.crossing_length());
+                assert!(events.iter().next().is_none());
+            }
+        }
+        // One step to step in, then as many as needed to walk the whole crosswalk
+        let walking = (config.crossing_length() / (config.pedestrian_speed * DT)).ceil() as i32;
+        assert_eq!(steps, 1 + walking);
+        assert!(pedestrian.walked >= config.crossing_length());
+        let events = events.drain();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].kind,
+            EventKind::PedestrianCrossed {
+                id: 3,
+                crosswalk: North,
+                waited: 6.0 * DT,
+            }
+        );
+    }
+
+    #[test]
+    fn vehicle_yields_to_a_crossing_pedestrian() {
+        let mut simulation = Simulation::new(Config::default(), 1);
+        let id = simulation
+            .spawn_vehicle(North, Some(Direction::Up))
+            .unwrap();
+        assert_eq!(Movement::new(North, Direction::Up).exit(), South);
+        let mut pedestrian = Pedestrian::new(1, South, false);
+        pedestrian.state = PedestrianState::Crossing;
+        simulation.pedestrians.push(pedestrian);
+
+        let mut held = false;
+        let mut passed = false;
+        while simulation.time() < 60.0 && !passed {
+            simulation.step();
+            let Some(vehicle) = simulation
+                .vehicles()
+                .into_iter()
+                .find(|vehicle| vehicle.id == id)
+            else {
+                break;
+            };
+            if simulation.pedestrians.iter().any(Pedestrian::is_crossing) {
+                assert!(
+                    vehicle.distance <= vehicle.path.crosswalk_entry(),
+                    "drove onto the crosswalk at {}s",
+                    simulation.time()
+                );
+                held |= vehicle.speed == 0.0;
+            } else {
+                passed = vehicle.distance > vehicle.path.crosswalk_exit();
+            }
+        }
+        assert!(held, "the vehicle never had to stop for the pedestrian");
+        assert!(
+            passed,
+            "the vehicle did not go on once the pedestrian crossed"
+        );
+    }
+}
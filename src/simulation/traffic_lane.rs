@@ -1,35 +1,58 @@
 use crate::{
-    config::{
-        BOTTOM_SPAWN, BOTTOM_VECTOR, Direction, LEFT_SPAWN, LEFT_VECTOR, RIGHT_SPAWN, RIGHT_VECTOR,
-        TOP_VECTOR, UP_SPAWN,
+    config::{Config, Direction, VehicleClass},
+    simulation::{
+        ApproachMap, ConflictMatrix, Movement, MovementMap, Vehicle, VehicleSpawn, VehicleState,
+        ZoneOccupancy, can_spawn_vehicle,
     },
-    simulation::{Vehicle, VehicleSpawn, can_spawn_vehicle},
 };
+use rand::Rng;
 use std::{
     collections::VecDeque,
     sync::{Arc, Mutex},
 };
 
-// Thread-safe vehicle lanes using Arc<Mutex<VecDeque<Vehicle>>>
+/// Thread-safe vehicle lanes using `Arc<Mutex<VecDeque<Vehicle>>>`
 pub type VehicleLane = Arc<Mutex<VecDeque<Vehicle>>>;
 
-// Four lanes for the 4 spawn points
+/// Vehicle asked for while there was no room at the spawn point, its class and turn already
+/// drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingVehicle {
+    pub class: VehicleClass,
+    pub direction: Direction,
+    /// The turn was drawn from the turning ratios, not asked for
+    pub drawn_turn: bool,
+    /// Time spent waiting to enter the road, in seconds
+    pub waited: f32,
+}
+
+/// Every lane of the 4 spawn points and of the 4 exit roads, from the median to the curb
+/// A vehicle stays in its approach lane through the junction, then moves to the exit road lane
+/// of the same rank until it reaches its destination
 pub struct TrafficLanes {
-    pub left: VehicleLane,
-    pub right: VehicleLane,
-    pub bottom: VehicleLane,
-    pub up: VehicleLane,
+    pub(crate) lanes: ApproachMap<Vec<VehicleLane>>,
+    // Lanes leaving the intersection, by the side they leave it through, each ordered from
+    // the front vehicle to the back one
+    pub(crate) exits: ApproachMap<Vec<VehicleLane>>,
+    // Virtual entry queue of each side, vehicles waiting off screen for room at the spawn point
+    // in the order they were asked for
+    pub(crate) backlog: ApproachMap<Mutex<VecDeque<PendingVehicle>>>,
     // To assign unique IDs to vehicles
     vehicle_id_counter: Arc<Mutex<i32>>,
 }
 
 impl TrafficLanes {
-    pub fn new() -> Self {
+    /// Empty lanes and backlogs, `config.lanes` lanes for every approach and exit road
+    pub fn new(config: &Config) -> Self {
+        let road = || {
+            (0..config.lanes)
+                .map(|_| Arc::new(Mutex::new(VecDeque::new())))
+                .collect()
+        };
         Self {
-            left: Arc::new(Mutex::new(VecDeque::new())),
-            right: Arc::new(Mutex::new(VecDeque::new())),
-            bottom: Arc::new(Mutex::new(VecDeque::new())),
-            up: Arc::new(Mutex::new(VecDeque::new())),
+            lanes: ApproachMap::from_fn(|_| road()),
+            exits: ApproachMap::from_fn(|_| road()),
+            backlog: ApproachMap::default(),
             vehicle_id_counter: Arc::new(Mutex::new(0)),
         }
     }
@@ -40,80 +63,391 @@ impl TrafficLanes {
         *counter
     }
 
-    // Method to spawn a vehicle to a given direction
-    pub fn spawn_vehicle(&self, spawn_point: &str) {
-        match spawn_point {
-            "up" => {
-                // Lock the Arc for safety
-                let mut up_lane = self.up.lock().unwrap();
-                if can_spawn_vehicle(&up_lane) {
-                    up_lane.push_back(Vehicle::new(
-                        self.get_next_vehicle_id(),
-                        UP_SPAWN,
-                        BOTTOM_VECTOR,
-                        Direction::random(),
-                        VehicleSpawn::North,
-                    ));
-                    // Lock ends when out of scope
-                }
+    /// Method to spawn a vehicle on a given side, its class and turn are drawn from `rng` with the
+    /// class mix and the turning ratios of the side, unless the turn is given
+    /// It enters the lane allowing its turn with the fewest vehicles, or any lane with room
+    /// when they are all full, changing lanes later on
+    /// Returns the id of the new vehicle, None if there is no room at the spawn point or vehicles
+    /// are already waiting for it, the vehicle then waits at the back of the backlog of the side
+    pub fn spawn_vehicle(
+        &self,
+        spawn: VehicleSpawn,
+        turn: Option<Direction>,
+        config: &Config,
+        rng: &mut impl Rng,
+    ) -> Option<i32> {
+        let class = config.class_mix[spawn].sample(rng);
+        self.spawn_vehicle_of_class(spawn, class, turn, config, rng)
+    }
+
+    /// Same as `spawn_vehicle` with the class given, whatever the class mix of the approach
+    pub fn spawn_vehicle_of_class(
+        &self,
+        spawn: VehicleSpawn,
+        class: VehicleClass,
+        turn: Option<Direction>,
+        config: &Config,
+        rng: &mut impl Rng,
+    ) -> Option<i32> {
+        let drawn_turn = turn.is_none();
+        let direction = turn.unwrap_or_else(|| config.turning_ratios(spawn).sample(rng));
+        let mut backlog = self.backlog[spawn].lock().unwrap();
+        let entered = backlog
+            .is_empty()
+            .then(|| self.enter(spawn, class, direction, drawn_turn, 0.0, config))
+            .flatten();
+        if entered.is_none() {
+            backlog.push_back(PendingVehicle {
+                class,
+                direction,
+                drawn_turn,
+                waited: 0.0,
+            });
+        }
+        entered
+    }
+
+    // Let the vehicles of the backlogs waiting for `dt` more seconds onto the road, as long as
+    // there is room for the first one of each side
+    // Returns the side and the id of every vehicle let in
+    pub(crate) fn release_backlog(&self, dt: f32, config: &Config) -> Vec<(VehicleSpawn, i32)> {
+        let mut released = Vec::new();
+        for (spawn, backlog) in self.backlog.iter() {
+            let mut backlog = backlog.lock().unwrap();
+            for pending in backlog.iter_mut() {
+                pending.waited += dt;
             }
-            "down" => {
-                let mut bottom_lane = self.bottom.lock().unwrap();
-                if can_spawn_vehicle(&bottom_lane) {
-                    bottom_lane.push_back(Vehicle::new(
-                        self.get_next_vehicle_id(),
-                        BOTTOM_SPAWN,
-                        TOP_VECTOR,
-                        Direction::random(),
-                        VehicleSpawn::South,
-                    ));
+            while let Some(pending) = backlog.front() {
+                let Some(id) = self.enter(
+                    spawn,
+                    pending.class,
+                    pending.direction,
+                    pending.drawn_turn,
+                    pending.waited,
+                    config,
+                ) else {
+                    break;
+                };
+                released.push((spawn, id));
+                backlog.pop_front();
+            }
+        }
+        released
+    }
+
+    /// Vehicles waiting in the backlog of the given side
+    pub fn backlog(&self, spawn: VehicleSpawn) -> Vec<PendingVehicle> {
+        self.backlog[spawn]
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    // Put a vehicle on the road at the spawn point when there is room for it, after
+    // `entry_delay` seconds in the backlog
+    fn enter(
+        &self,
+        spawn: VehicleSpawn,
+        class: VehicleClass,
+        direction: Direction,
+        drawn_turn: bool,
+        entry_delay: f32,
+        config: &Config,
+    ) -> Option<i32> {
+        // Lock the Arcs for safety
+        let mut lanes: Vec<_> = self.lanes[spawn]
+            .iter()
+            .map(|lane| lane.lock().unwrap())
+            .collect();
+        // Vehicles moving across from the next lane are part of the lane already
+        let last = |lane: usize| {
+            lanes
+                .iter()
+                .flat_map(|vehicles| vehicles.iter())
+                .filter(|vehicle| vehicle.is_in_lane(lane))
+                .min_by(|a, b| a.distance.total_cmp(&b.distance))
+        };
+        let free: Vec<usize> = (0..lanes.len())
+            .filter(|&lane| can_spawn_vehicle(last(lane), class, config))
+            .collect();
+        if free.is_empty() {
+            return None;
+        }
+        let uses = config.lane_uses(spawn);
+        let lane = free
+            .into_iter()
+            .min_by_key(|&lane| (!uses[lane].allows(direction), lanes[lane].len()))?;
+        let id = self.get_next_vehicle_id();
+        let mut vehicle = Vehicle::new(id, class, direction, spawn, lane, config);
+        vehicle.drawn_turn = drawn_turn;
+        vehicle.entry_delay = entry_delay;
+        lanes[lane].push_back(vehicle);
+        // Locks end when out of scope
+        Some(id)
+    }
+
+    /// Approach lanes first, then the exit road lanes
+    pub fn every_lane(&self) -> impl Iterator<Item = &VehicleLane> {
+        self.lanes.values().chain(self.exits.values()).flatten()
+    }
+
+    // Move the vehicles out of the junction to the exit road they left it through, in the lane
+    // of the same rank, behind the vehicles closer to the end of the road
+    // Returns the movement of every vehicle moved, with its turn as it really took it, and
+    // whether that turn was drawn from the turning ratios
+    pub(crate) fn leave_junction(&self) -> Vec<(Movement, bool)> {
+        let mut left = Vec::new();
+        for lanes in self.lanes.values() {
+            for (rank, lane) in lanes.iter().enumerate() {
+                let mut lane = lane.lock().unwrap();
+                while let Some(index) = lane
+                    .iter()
+                    .position(|vehicle| vehicle.state == VehicleState::Exiting)
+                {
+                    let vehicle = lane.remove(index).unwrap();
+                    left.push((vehicle.movement(), vehicle.drawn_turn));
+                    let mut road = self.exits[vehicle.movement().exit()][rank].lock().unwrap();
+                    let at = road
+                        .iter()
+                        .position(|other| other.remaining() > vehicle.remaining())
+                        .unwrap_or(road.len());
+                    road.insert(at, vehicle);
                 }
             }
-            "left" => {
-                let mut left_lane = self.left.lock().unwrap();
-                if can_spawn_vehicle(&left_lane) {
-                    left_lane.push_back(Vehicle::new(
-                        self.get_next_vehicle_id(),
-                        LEFT_SPAWN,
-                        RIGHT_VECTOR,
-                        Direction::random(),
-                        VehicleSpawn::West,
-                    ));
+        }
+        left
+    }
+
+    /// Vehicles of every approach lane of the given side
+    pub fn vehicles(&self, spawn: VehicleSpawn) -> Vec<Vehicle> {
+        self.lanes[spawn]
+            .iter()
+            .flat_map(|lane| lane.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Number of vehicles queued behind the stop line of the given side
+    pub fn queue_length(&self, spawn: VehicleSpawn) -> usize {
+        self.lanes[spawn]
+            .iter()
+            .map(|lane| {
+                let lane = lane.lock().unwrap();
+                lane.iter()
+                    .filter(|vehicle| vehicle.state.is_queued())
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Number of vehicles inside the junction box
+    pub fn junction_occupancy(&self) -> usize {
+        self.every_lane()
+            .map(|lane| {
+                let lane = lane.lock().unwrap();
+                lane.iter()
+                    .filter(|vehicle| vehicle.state.is_in_junction())
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Number of vehicles queued behind the stop line, by the movement they are going to do
+    pub fn movement_queues(&self) -> MovementMap<usize> {
+        let mut queues = MovementMap::default();
+        for lane in self.lanes.values().flatten() {
+            for vehicle in lane.lock().unwrap().iter() {
+                if vehicle.state.is_queued() {
+                    queues[vehicle.movement()] += 1;
                 }
             }
-            _ => {
-                // "right"
-                let mut right_lane = self.right.lock().unwrap();
-                if can_spawn_vehicle(&right_lane) {
-                    right_lane.push_back(Vehicle::new(
-                        self.get_next_vehicle_id(),
-                        RIGHT_SPAWN,
-                        LEFT_VECTOR,
-                        Direction::random(),
-                        VehicleSpawn::East,
-                    ));
+        }
+        queues
+    }
+
+    /// Movements of the vehicles queued on the given side, lane by lane from the median, each
+    /// from the stop line backwards
+    pub fn waiting_movements(&self, spawn: VehicleSpawn) -> Vec<Vec<Movement>> {
+        self.lanes[spawn]
+            .iter()
+            .map(|lane| {
+                lane.lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|vehicle| vehicle.state.is_queued())
+                    .map(|vehicle| vehicle.movement())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Movements of the vehicles inside each conflict zone of the junction box
+    pub fn zone_occupancy(&self, conflicts: &ConflictMatrix) -> ZoneOccupancy {
+        let mut zones = vec![Vec::new(); conflicts.zone_count()];
+        for lane in self.every_lane() {
+            for vehicle in lane.lock().unwrap().iter() {
+                let footprint = vehicle.corners();
+                for zone in conflicts.zones_at(&footprint) {
+                    zones[zone].push(vehicle.movement());
                 }
             }
         }
+        zones
     }
 
-    // Method to get total vehicle count across all lanes (useful for debugging)
-    pub fn _total_vehicle_count(&self) -> usize {
-        let up_count = self.up.lock().unwrap().len();
-        let bottom_count = self.bottom.lock().unwrap().len();
-        let left_count = self.left.lock().unwrap().len();
-        let right_count = self.right.lock().unwrap().len();
+    /// Number of vehicles spawned since the start
+    pub fn spawned_count(&self) -> i32 {
+        *self.vehicle_id_counter.lock().unwrap()
+    }
 
-        up_count + bottom_count + left_count + right_count
+    /// Number of vehicles on every approach and exit road lane
+    pub fn total_vehicle_count(&self) -> usize {
+        self.every_lane()
+            .map(|lane| lane.lock().unwrap().len())
+            .sum()
     }
 
-    // Method to get vehicle counts for each lane (useful for debugging)
-    pub fn _get_lane_counts(&self) -> (usize, usize, usize, usize) {
-        let up_count = self.up.lock().unwrap().len();
-        let bottom_count = self.bottom.lock().unwrap().len();
-        let left_count = self.left.lock().unwrap().len();
-        let right_count = self.right.lock().unwrap().len();
+    /// Number of vehicles on the approach lanes of each side
+    pub fn get_lane_counts(&self) -> ApproachMap<usize> {
+        ApproachMap::from_fn(|spawn| {
+            self.lanes[spawn]
+                .iter()
+                .map(|lane| lane.lock().unwrap().len())
+                .sum()
+        })
+    }
+}
 
-        (up_count, bottom_count, left_count, right_count)
+impl Default for TrafficLanes {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Move every vehicle of the side far enough from the spawn point to make room behind it
+    fn make_room(lanes: &TrafficLanes, spawn: VehicleSpawn) {
+        for lane in &lanes.lanes[spawn] {
+            for vehicle in lane.lock().unwrap().iter_mut() {
+                vehicle.distance += 1000.0;
+            }
+        }
+    }
+
+    fn waiting_turns(lanes: &TrafficLanes, spawn: VehicleSpawn) -> Vec<Direction> {
+        lanes
+            .backlog(spawn)
+            .iter()
+            .map(|pending| pending.direction)
+            .collect()
+    }
+
+    #[test]
+    fn backlog_lets_vehicles_in_in_the_order_they_were_asked_for() {
+        let config = Config::default();
+        let lanes = TrafficLanes::new(&config);
+        let mut rng = StdRng::seed_from_u64(1);
+        let north = VehicleSpawn::North;
+        let mut spawn = |turn| {
+            lanes.spawn_vehicle_of_class(north, VehicleClass::Car, Some(turn), &config, &mut rng)
+        };
+        assert!(spawn(Direction::Left).is_some());
+        for turn in [Direction::Up, Direction::Right, Direction::Left] {
+            assert_eq!(spawn(turn), None);
+        }
+        assert_eq!(
+            waiting_turns(&lanes, north),
+            [Direction::Up, Direction::Right, Direction::Left]
+        );
+
+        // Without room nobody enters, every vehicle keeps waiting
+        assert!(lanes.release_backlog(0.5, &config).is_empty());
+        make_room(&lanes, north);
+        let released = lanes.release_backlog(0.5, &config);
+        assert_eq!(released.len(), 1);
+        let vehicles = lanes.vehicles(north);
+        let entered = vehicles
+            .iter()
+            .find(|vehicle| vehicle.id == released[0].1)
+            .unwrap();
+        assert_eq!(entered.direction, Direction::Up);
+        assert_eq!(entered.entry_delay, 1.0);
+
+        // A vehicle asked for while others wait goes behind them, even with room for it
+        make_room(&lanes, north);
+        assert_eq!(spawn(Direction::Up), None);
+        assert_eq!(
+            waiting_turns(&lanes, north),
+            [Direction::Right, Direction::Left, Direction::Up]
+        );
+        assert_eq!(lanes.backlog(north)[0].waited, 1.0);
+    }
+
+    #[test]
+    fn vehicles_merging_onto_an_exit_road_follow_each_other() {
+        let mut simulation = Simulation::new(Config::default(), 1);
+        let south = VehicleSpawn::South;
+        let id = simulation
+            .spawn_vehicle(VehicleSpawn::North, Some(Direction::Up))
+            .unwrap();
+        // Wrecked on the exit road a bit past the junction, the next vehicles queue behind it
+        let mut wrecked = false;
+        while !wrecked && simulation.time() < 30.0 {
+            simulation.step();
+            for lane in &simulation.lanes.exits[south] {
+                for vehicle in lane.lock().unwrap().iter_mut() {
+                    if vehicle.id == id && vehicle.distance > vehicle.path.junction_exit() + 350.0 {
+                        vehicle.state = VehicleState::Crashed;
+                        vehicle.clearance = f32::INFINITY;
+                        vehicle.speed = 0.0;
+                        wrecked = true;
+                    }
+                }
+            }
+        }
+        assert!(wrecked, "the first vehicle never reached the exit road");
+        // Right from West and left from East leave through South as well
+        for (spawn, turn) in [
+            (VehicleSpawn::West, Direction::Right),
+            (VehicleSpawn::East, Direction::Left),
+            (VehicleSpawn::West, Direction::Right),
+        ] {
+            simulation.spawn_vehicle(spawn, Some(turn));
+        }
+
+        let mut queued = Vec::new();
+        while simulation.time() < 90.0 {
+            simulation.step();
+            for lane in &simulation.lanes.exits[south] {
+                let lane = lane.lock().unwrap();
+                for (front, back) in lane.iter().zip(lane.iter().skip(1)) {
+                    // Every exit road lane ends at the same point whatever the way in
+                    let gap = back.remaining()
+                        - front.remaining()
+                        - (front.height + back.height) as f32 / 2.0;
+                    assert!(
+                        gap > 0.0,
+                        "vehicle {} overlaps vehicle {} by {} at {}s",
+                        back.id,
+                        front.id,
+                        -gap,
+                        simulation.time()
+                    );
+                }
+                queued = lane.iter().map(|vehicle| vehicle.spawn).collect();
+            }
+        }
+        // Everybody stopped behind the wreck, in the order they got there
+        assert_eq!(queued.len(), 4);
+        assert_eq!(queued[0], VehicleSpawn::North);
+        assert!(queued.contains(&VehicleSpawn::West) && queued.contains(&VehicleSpawn::East));
+        assert!(simulation.collisions.crashes.is_empty());
     }
 }
@@ -1,30 +1,421 @@
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
+use crate::config::{Config, Direction};
 
-#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+/// Aspects a traffic light can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SignalState {
+    #[default]
+    Red,
+    /// Announces the green, vehicles must still wait
+    RedAmber,
+    Green,
+    /// Announces the red, vehicles stop if they still can
+    Amber,
+    /// Light out of its cycle, vehicles give way before entering the junction
+    FlashingAmber,
+    /// Dark light, same rule as flashing amber
+    Off,
+}
+
+impl SignalState {
+    /// Light out of its normal cycle, the junction works as give way for everybody
+    pub fn is_uncontrolled(self) -> bool {
+        matches!(self, SignalState::FlashingAmber | SignalState::Off)
+    }
+}
+
+/// Signal shown to a single movement, walking the cycle on its own timing
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SignalHead {
+    pub(crate) state: SignalState,
+    // Seconds spent in the current state
+    pub(crate) timer: f32,
+    // Last order of the controller, the light walks its cycle towards it
+    wants_green: bool,
+    // Green given without right of way, vehicles yield to the opposing traffic
+    pub(crate) permissive: bool,
+    wants_permissive: bool,
+}
+
+impl SignalHead {
+    pub fn state(&self) -> SignalState {
+        self.state
+    }
+
+    /// Seconds spent in the current state
+    pub fn elapsed(&self) -> f32 {
+        self.timer
+    }
+
+    pub fn is_green(&self) -> bool {
+        self.state == SignalState::Green
+    }
+
+    pub fn is_red(&self) -> bool {
+        self.state == SignalState::Red
+    }
+
+    /// Showing a permissive aspect, vehicles must yield to the opposing traffic
+    pub fn is_permissive(&self) -> bool {
+        self.permissive
+            && matches!(
+                self.state,
+                SignalState::RedAmber | SignalState::Green | SignalState::Amber
+            )
+    }
+
+    /// Whether the head shows or walks towards a permissive green
+    pub fn wants_permissive(&self) -> bool {
+        self.wants_green && self.wants_permissive
+    }
+
+    /// Start the cycle towards a protected green, through red-amber
+    pub fn request_green(&mut self) {
+        self.wants_green = true;
+        self.wants_permissive = false;
+        if self.state.is_uncontrolled() {
+            self.set_state(SignalState::Red);
+        }
+    }
+
+    /// Start the cycle towards a permissive green, through red-amber
+    pub fn request_permissive(&mut self) {
+        self.request_green();
+        self.wants_permissive = true;
+    }
+
+    /// Start the cycle towards red, through amber
+    pub fn request_red(&mut self) {
+        self.wants_green = false;
+        if self.state.is_uncontrolled() {
+            self.set_state(SignalState::Red);
+        }
+    }
+
+    /// Out of the cycle, flashing amber until asked for a green or a red
+    pub fn set_flashing(&mut self) {
+        self.wants_green = false;
+        self.set_state(SignalState::FlashingAmber);
+    }
+
+    /// Out of the cycle, dark until asked for a green or a red
+    pub fn switch_off(&mut self) {
+        self.wants_green = false;
+        self.set_state(SignalState::Off);
+    }
+
+    fn set_state(&mut self, state: SignalState) {
+        if self.state != state {
+            self.state = state;
+            self.timer = 0.0;
+        }
+    }
+
+    // Timed transitions of the cycle Red -> RedAmber -> Green -> Amber -> Red
+    // `may_start` tells if the all-red clearance allows this light to leave red, it is checked
+    // again before the green and to turn a permissive green into a protected one
+    pub(crate) fn update(&mut self, dt: f32, config: &Config, may_start: bool) {
+        self.timer += dt;
+        match self.state {
+            SignalState::Red if self.wants_green && may_start => {
+                self.permissive = self.wants_permissive;
+                self.set_state(SignalState::RedAmber)
+            }
+            SignalState::RedAmber if !self.wants_green => self.set_state(SignalState::Red),
+            // Started as permissive, the protected clearance has to be checked again from red
+            SignalState::RedAmber if self.permissive && !self.wants_permissive => {
+                self.set_state(SignalState::Red)
+            }
+            SignalState::RedAmber if self.timer >= config.red_amber_time && may_start => {
+                self.permissive = self.wants_permissive;
+                self.set_state(SignalState::Green)
+            }
+            SignalState::Green if !self.wants_green => self.set_state(SignalState::Amber),
+            // Giving up the right of way is always safe, taking it needs the clearance
+            SignalState::Green
+                if self.permissive != self.wants_permissive
+                    && (self.wants_permissive || may_start) =>
+            {
+                self.permissive = self.wants_permissive
+            }
+            SignalState::Amber if self.timer >= config.amber_time => {
+                self.set_state(SignalState::Red)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Aspects of the pedestrian signal of a crosswalk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WalkState {
+    #[default]
+    DontWalk,
+    Walk,
+    /// Pedestrians must not step in, the ones crossing have the time to finish
+    FlashingDontWalk,
+}
+
+/// Pedestrian signal of a crosswalk, with its push button
+/// Shows walk once per call, for `walk_time`, when the controller allows it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PedestrianSignal {
+    state: WalkState,
+    // Seconds spent in the current state
+    timer: f32,
+    // Last order of the controller
+    wants_walk: bool,
+    // Push button pressed since the last walk
+    called: bool,
+}
+
+impl PedestrianSignal {
+    pub fn state(&self) -> WalkState {
+        self.state
+    }
+
+    /// Seconds spent in the current state
+    pub fn elapsed(&self) -> f32 {
+        self.timer
+    }
+
+    pub fn is_walk(&self) -> bool {
+        self.state == WalkState::Walk
+    }
+
+    pub fn is_dont_walk(&self) -> bool {
+        self.state == WalkState::DontWalk
+    }
+
+    /// Whether a pedestrian is waiting for the next walk
+    pub fn is_called(&self) -> bool {
+        self.called
+    }
+
+    /// Push button
+    pub fn call(&mut self) {
+        self.called = true;
+    }
+
+    /// Show the walk at the next push button call, once the conflicting heads are clear
+    pub fn request_walk(&mut self) {
+        self.wants_walk = true;
+    }
+
+    /// A walk already shown is not cut short
+    pub fn request_dont_walk(&mut self) {
+        self.wants_walk = false;
+    }
+
+    fn set_state(&mut self, state: WalkState) {
+        if self.state != state {
+            self.state = state;
+            self.timer = 0.0;
+        }
+    }
+
+    // Timed transitions of the cycle DontWalk -> Walk -> FlashingDontWalk -> DontWalk
+    // `may_start` tells if every conflicting head is clear, as for a vehicle head
+    pub(crate) fn update(&mut self, dt: f32, config: &Config, may_start: bool) {
+        self.timer += dt;
+        match self.state {
+            WalkState::DontWalk if self.wants_walk && self.called && may_start => {
+                self.called = false;
+                self.set_state(WalkState::Walk)
+            }
+            WalkState::Walk if self.timer >= config.walk_time => {
+                self.set_state(WalkState::FlashingDontWalk)
+            }
+            WalkState::FlashingDontWalk if self.timer >= config.crossing_time() => {
+                self.set_state(WalkState::DontWalk)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Lights of an approach, one signal head per turn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct TrafficLight {
-    pub(crate) rect: Rect,
-    pub(crate) state: bool,
+    heads: [SignalHead; 3],
 }
 
 impl TrafficLight {
-    pub fn new(rect: Rect) -> Self {
-        Self { rect, state: false }
+    /// Every head red
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn change_state(&mut self, state: Option<bool>) {
-        self.state = state.unwrap_or(!self.state)
+    /// Head controlling the vehicles taking the given turn
+    pub fn head(&self, turn: Direction) -> &SignalHead {
+        &self.heads[turn.index()]
     }
-    pub fn draw(&mut self, canvas: &mut WindowCanvas) -> Result<(), String> {
-        match self.state {
-            true => canvas.set_draw_color(Color::GREEN),
-            false => {
-                canvas.set_draw_color(Color::RED);
+
+    pub(crate) fn head_mut(&mut self, turn: Direction) -> &mut SignalHead {
+        &mut self.heads[turn.index()]
+    }
+
+    /// At least one turn of the approach is green
+    pub fn is_green(&self) -> bool {
+        self.heads.iter().any(SignalHead::is_green)
+    }
+
+    /// Every turn of the approach is red
+    pub fn is_red(&self) -> bool {
+        self.heads.iter().all(SignalHead::is_red)
+    }
+
+    /// Every head of the approach flashing amber
+    pub fn set_flashing(&mut self) {
+        self.heads.iter_mut().for_each(SignalHead::set_flashing);
+    }
+
+    /// Every head of the approach dark
+    pub fn switch_off(&mut self) {
+        self.heads.iter_mut().for_each(SignalHead::switch_off);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Step length, exact in binary so the timers hit the configured durations
+    const DT: f32 = 0.25;
+
+    fn config() -> Config {
+        Config {
+            amber_time: 3.0,
+            red_amber_time: 1.5,
+            all_red_time: 2.0,
+            walk_time: 4.0,
+            ..Config::default()
+        }
+    }
+
+    // States taken over `seconds` of steps, with the time each one was entered
+    fn transitions<S: Copy + PartialEq>(
+        seconds: f32,
+        mut step: impl FnMut(f32) -> S,
+    ) -> Vec<(S, f32)> {
+        let mut log: Vec<(S, f32)> = Vec::new();
+        for i in 1..=(seconds / DT) as u32 {
+            let time = i as f32 * DT;
+            let state = step(time);
+            if log.last().is_none_or(|&(last, _)| last != state) {
+                log.push((state, time));
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn head_walks_the_cycle_with_the_configured_durations() {
+        let config = config();
+        let mut head = SignalHead::default();
+        head.request_green();
+        let log = transitions(20.0, |time| {
+            if time > 10.0 {
+                head.request_red();
             }
-        };
-        canvas.draw_rect(self.rect)?;
+            head.update(DT, &config, true);
+            head.state()
+        });
+        assert_eq!(
+            log,
+            vec![
+                (SignalState::RedAmber, 0.25),
+                (SignalState::Green, 1.75),
+                (SignalState::Amber, 10.25),
+                (SignalState::Red, 13.25),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_head_waits_the_all_red_before_its_red_amber() {
+        let config = config();
+        let mut leaving = SignalHead::default();
+        leaving.request_green();
+        let mut entering = SignalHead::default();
+        for _ in 0..10 {
+            leaving.update(DT, &config, true);
+        }
+        assert!(leaving.is_green());
+
+        leaving.request_red();
+        entering.request_green();
+        // Same clearance rule as the engine for two conflicting heads
+        let log = transitions(15.0, |_| {
+            leaving.update(DT, &config, true);
+            let may_start = leaving.is_red() && leaving.elapsed() >= config.all_red_time;
+            entering.update(DT, &config, may_start);
+            (leaving.state(), entering.state())
+        });
+        assert_eq!(
+            log,
+            vec![
+                ((SignalState::Amber, SignalState::Red), 0.25),
+                ((SignalState::Red, SignalState::Red), 3.25),
+                ((SignalState::Red, SignalState::RedAmber), 5.25),
+                ((SignalState::Red, SignalState::Green), 6.75),
+            ]
+        );
+    }
+
+    #[test]
+    fn head_leaves_red_only_when_allowed_to_start() {
+        let config = config();
+        let mut head = SignalHead::default();
+        head.request_green();
+        for _ in 0..20 {
+            head.update(DT, &config, false);
+        }
+        assert!(head.is_red());
+        // Red-amber held until the clearance allows the green
+        head.update(DT, &config, true);
+        for _ in 0..20 {
+            head.update(DT, &config, false);
+        }
+        assert_eq!(head.state(), SignalState::RedAmber);
+        head.update(DT, &config, true);
+        assert!(head.is_green());
+    }
+
+    #[test]
+    fn pedestrian_signal_flashes_for_the_crossing_time_after_the_walk() {
+        let config = config();
+        let mut signal = PedestrianSignal::default();
+        signal.request_walk();
+        // No walk without a push button call, nor while a conflicting head is not clear
+        for _ in 0..10 {
+            signal.update(DT, &config, true);
+        }
+        signal.call();
+        for _ in 0..10 {
+            signal.update(DT, &config, false);
+        }
+        assert!(signal.is_dont_walk() && signal.is_called());
 
-        Ok(())
+        let flashing = config.walk_time + DT;
+        let log = transitions(30.0, |_| {
+            signal.update(DT, &config, true);
+            signal.state()
+        });
+        let states: Vec<WalkState> = log.iter().map(|&(state, _)| state).collect();
+        assert_eq!(
+            states,
+            vec![
+                WalkState::Walk,
+                WalkState::FlashingDontWalk,
+                WalkState::DontWalk
+            ]
+        );
+        assert_eq!(log[0].1, DT);
+        assert_eq!(log[1].1, flashing);
+        // Flashing for the crossing time, to the first step past it
+        let flashed = log[2].1 - flashing;
+        assert!(flashed >= config.crossing_time() && flashed < config.crossing_time() + DT);
+        // The call is answered, the next walk needs a new one
+        assert!(!signal.is_called());
     }
 }
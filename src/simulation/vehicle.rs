@@ -1,348 +1,883 @@
-use crate::config::{
-    // Add destination constants
-    BOTTOM_DESTINATION,
-    CASE_SIZE,
-    Direction,
-    EAST_LIGHT,
-    LEFT_DESTINATION,
-    NORTH_LIGHT,
-    RIGHT_DESTINATION,
-    SAFE_DISTANCE,
-    SOUTH_LIGHT,
-    UP_DESTINATION,
-    VEHICLE_HEIGHT,
-    VEHICLE_SPEED,
-    VEHICLE_WIDTH,
-    WEST_LIGHT,
+use crate::config::{Config, Direction, VehicleClass};
+use crate::simulation::{
+    ApproachMap, ConflictMatrix, Driver, EventKind, EventLog, Movement, Obstacle, Path, Pedestrian,
+    PedestrianSignal, PedestrianState, SignalState, TrafficLight, VehicleSpawn,
 };
-use crate::simulation::TrafficLight;
-use sdl2;
-use sdl2::rect::Rect;
-use std::collections::{HashMap, HashSet, VecDeque};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum VehicleSpawn {
-    North,
-    South,
-    East,
-    West,
+use std::sync::Arc;
+
+// Below this speed, in pixels per second, a vehicle behind a queue is part of it
+const QUEUE_SPEED: f32 = 10.0;
+// Below this speed, in pixels per second, a braking vehicle comes to a full stop
+const STOP_SPEED: f32 = 1.0;
+// A vehicle brakes for a point this far past its stop line, in pixels, so that it reaches the
+// line at a crawl instead of creeping towards it for ever, and stops once on it
+const LINE_OVERSHOOT: f32 = 5.0;
+// Share of its max acceleration a stopped driver must be offered before moving off
+const MOVE_OFF_ACCELERATION: f32 = 0.1;
+
+/// Corners of a vehicle, or of any rectangle, going around it
+pub type Footprint = [(f32, f32); 4];
+
+// Shared state of the intersection for a single simulation step
+pub(crate) struct StepContext<'a> {
+    pub dt: f32,
+    pub config: &'a Config,
+    pub lights: &'a ApproachMap<TrafficLight>,
+    pub conflicts: &'a ConflictMatrix,
+    /// Every vehicle as it was at the start of the step
+    pub vehicles: &'a [Vehicle],
+    pub crosswalks: &'a ApproachMap<PedestrianSignal>,
+    pub pedestrians: &'a [Pedestrian],
+    /// Vehicles of every exit road lane as they were at the start of the step, by the side they
+    /// leave through
+    pub exits: &'a ApproachMap<Vec<&'a [Vehicle]>>,
+    /// Vehicles inside the junction box, kept up to date as vehicles enter and leave it
+    pub junction_occupancy: usize,
+    /// Vehicles that crossed their stop line during this step, as they crossed it
+    pub entered: Vec<Vehicle>,
 }
 
-impl VehicleSpawn {
-    pub fn as_str(&self) -> &str {
-        match self {
-            VehicleSpawn::North => "North",
-            VehicleSpawn::South => "South",
-            VehicleSpawn::East => "East",
-            VehicleSpawn::West => "West",
-        }
+/// Lifecycle of a vehicle, from its spawn to its removal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VehicleState {
+    /// Driving towards the stop line
+    Approaching,
+    /// Stopped behind a queued vehicle, stays queued until it crosses the stop line
+    Queued,
+    /// First of the queue, waiting at the stop line for the green light
+    StoppedAtLine,
+    /// Crossed the stop line, inside the junction box
+    InJunction,
+    /// Inside the junction box, on the curve of its turn or past it
+    Turning,
+    /// Out of the junction box, driving to its destination
+    Exiting,
+    /// Ran into another vehicle, stopped where it is until the wreck is cleared
+    Crashed,
+    /// Reached its destination, removed at the end of the step
+    Despawned,
+}
+
+impl VehicleState {
+    /// Transitions allowed by the lifecycle
+    pub fn can_become(self, next: VehicleState) -> bool {
+        use VehicleState::*;
+        matches!(
+            (self, next),
+            (Approaching, Queued | StoppedAtLine | InJunction)
+                | (Queued, StoppedAtLine | InJunction)
+                | (StoppedAtLine, InJunction)
+                | (InJunction, Turning | Exiting)
+                | (Turning, Exiting)
+                | (Exiting, Despawned)
+                | (Crashed, Despawned)
+                | (
+                    Approaching | Queued | StoppedAtLine | InJunction | Turning | Exiting,
+                    Crashed
+                )
+        )
+    }
+
+    /// Waiting for the light, counted in the queue of its approach
+    pub fn is_queued(self) -> bool {
+        matches!(self, VehicleState::Queued | VehicleState::StoppedAtLine)
+    }
+
+    /// Occupying the junction box
+    pub fn is_in_junction(self) -> bool {
+        matches!(self, VehicleState::InJunction | VehicleState::Turning)
     }
 }
 
+/// Vehicle on the road, from its spawn point to its destination
+/// Only the simulation moves it, where it is and what it is doing are read through its getters
 #[derive(Debug, Clone)]
 pub struct Vehicle {
+    /// Unique among the vehicles of a simulation, in the order they were spawned
     pub id: i32,
-    pub position: (f32, f32),
-    pub vector: (f32, f32),
-    pub speed: u16,
+    /// Longitudinal behaviour of its driver, from its class
+    pub driver: Driver,
+    /// Size across the heading
+    pub width: u32,
+    /// Size along the heading
+    pub height: u32,
+    /// Side it came from
     pub spawn: VehicleSpawn,
-    pub direction: Direction,
-    pub shape: sdl2::rect::Rect,
-    pub as_turned: bool,
+    pub class: VehicleClass,
+    // Center of the vehicle and unit heading, both following the path
+    pub(crate) position: (f32, f32),
+    pub(crate) vector: (f32, f32),
+    // Pixels per second along the path
+    pub(crate) speed: f32,
+    // Lane of the approach, counted from the median, kept through the junction
+    pub(crate) lane: usize,
+    pub(crate) direction: Direction,
+    pub(crate) state: VehicleState,
+    // Trajectory from the spawn point in its lane to the destination
+    pub(crate) path: Arc<Path>,
+    // Distance to the right of the path while moving across from the previous lane, in pixels
+    pub(crate) lateral: f32,
+    // Lane the vehicle has to get into and is waiting for a gap in, the vehicles of that lane
+    // let it in
+    pub(crate) wants_lane: Option<usize>,
+    // Arc length travelled along the path
+    pub(crate) distance: f32,
+    // Braking for the stop line, kept through an amber once decided
+    pub(crate) stopping: bool,
+    // Time spent at a standstill with the way ahead clear, until the driver reacts
+    pub(crate) waiting: f32,
+    // Time left before the wreck is cleared from the road, while Crashed
+    pub(crate) clearance: f32,
+    // Time spent in the backlog of its side waiting for room at the spawn point, in seconds
+    pub(crate) entry_delay: f32,
+    // Turn drawn from the turning ratios of its side, not asked for at its spawn
+    pub(crate) drawn_turn: bool,
 }
 
-impl<'a> Vehicle {
+impl Vehicle {
+    /// Vehicle of the given class at the spawn point of its lane, driving in at its desired
+    /// speed
     pub fn new(
         id: i32,
-        position: (f32, f32),
-        vector: (f32, f32),
+        class: VehicleClass,
         direction: Direction,
         spawn: VehicleSpawn,
+        lane: usize,
+        config: &Config,
     ) -> Self {
-        let mut vehicle = Vehicle {
+        let spec = config.classes.get(class);
+        let path = Arc::new(Path::new(
+            Movement::new(spawn, direction),
+            lane,
+            spec.length,
+            config,
+        ));
+        let (position, vector) = path.at(0.0);
+        let driver = Driver::new(spec, config);
+        // Vehicles enter the screen at their desired speed
+        Vehicle {
             id,
             position,
             vector,
-            speed: VEHICLE_SPEED,
+            speed: driver.desired_speed,
+            driver,
+            width: spec.width,
+            height: spec.length,
             direction,
             spawn,
-            shape: Rect::new(
-                position.0 as i32 - (VEHICLE_WIDTH / 2) as i32,
-                position.1 as i32 - (VEHICLE_HEIGHT / 2) as i32,
-                VEHICLE_WIDTH,
-                VEHICLE_HEIGHT,
-            ),
-            as_turned: false,
-        };
-        vehicle.accelerate();
-        vehicle
+            lane,
+            class,
+            state: VehicleState::Approaching,
+            path,
+            lateral: 0.0,
+            wants_lane: None,
+            distance: 0.0,
+            stopping: false,
+            waiting: 0.0,
+            clearance: 0.0,
+            entry_delay: 0.0,
+            drawn_turn: false,
+        }
+    }
+
+    /// Center of the vehicle
+    pub fn position(&self) -> (f32, f32) {
+        self.position
+    }
+
+    /// Unit heading, following the path
+    pub fn vector(&self) -> (f32, f32) {
+        self.vector
+    }
+
+    /// Pixels per second along the path
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Lane of the approach, counted from the median, kept through the junction
+    pub fn lane(&self) -> usize {
+        self.lane
+    }
+
+    /// Turn taken at the junction
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Where it is in its lifecycle
+    pub fn state(&self) -> VehicleState {
+        self.state
+    }
+
+    /// Trajectory from the spawn point in its lane to the destination
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Arc length travelled along the path
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Time spent in the backlog of its side waiting for room at the spawn point, in seconds
+    pub fn entry_delay(&self) -> f32 {
+        self.entry_delay
+    }
+
+    /// Corners of the vehicle turned along its heading: front left, front right, rear right,
+    /// rear left
+    pub fn corners(&self) -> Footprint {
+        self.corners_at(self.position, self.vector)
+    }
+
+    // Corners the vehicle would have with its center at `distance` along its path
+    fn corners_on_path(&self, distance: f32) -> Footprint {
+        let (position, vector) = self.path.at(distance);
+        self.corners_at(position, vector)
     }
 
-    pub fn render(&self, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
-        canvas.set_draw_color(self.direction.color());
-        let _ = canvas.fill_rect(self.shape);
+    fn corners_at(&self, position: (f32, f32), vector: (f32, f32)) -> Footprint {
+        let (x, y) = position;
+        let (hx, hy) = vector;
+        let half_length = self.height as f32 / 2.0;
+        let half_width = self.width as f32 / 2.0;
+        let corner =
+            |along: f32, across: f32| (x + hx * along - hy * across, y + hy * along + hx * across);
+        [
+            corner(half_length, -half_width),
+            corner(half_length, half_width),
+            corner(-half_length, half_width),
+            corner(-half_length, -half_width),
+        ]
     }
 
-    // Check if vehicle has reached its destination
+    /// Bounding box of the vehicle as (x, y, width, height), top-left corner first
+    pub fn shape(&self) -> (i32, i32, u32, u32) {
+        let corners = self.corners();
+        let (mut min_x, mut min_y) = corners[0];
+        let (mut max_x, mut max_y) = corners[0];
+        for &(x, y) in &corners[1..] {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        (
+            min_x.round() as i32,
+            min_y.round() as i32,
+            (max_x - min_x).round() as u32,
+            (max_y - min_y).round() as u32,
+        )
+    }
+
+    /// Check if vehicle has reached its destination
     pub fn has_reached_destination(&self) -> bool {
-        let destination = self.get_destination();
-        let distance = ((self.position.0 - destination.0).powi(2)
-            + (self.position.1 - destination.1).powi(2))
-        .sqrt();
-
-        // Consider destination reached if within 50 pixels
-        distance < 50.0
-    }
-
-    // Get the destination based on spawn point and direction
-    fn get_destination(&self) -> (f32, f32) {
-        match self.spawn {
-            VehicleSpawn::North => match self.direction {
-                Direction::Up => UP_DESTINATION,
-                Direction::Left => LEFT_DESTINATION,
-                Direction::Right => RIGHT_DESTINATION,
-            },
-            VehicleSpawn::South => match self.direction {
-                Direction::Up => BOTTOM_DESTINATION,
-                Direction::Left => RIGHT_DESTINATION,
-                Direction::Right => LEFT_DESTINATION,
-            },
-            VehicleSpawn::East => match self.direction {
-                Direction::Up => RIGHT_DESTINATION,
-                Direction::Left => UP_DESTINATION,
-                Direction::Right => BOTTOM_DESTINATION,
-            },
-            VehicleSpawn::West => match self.direction {
-                Direction::Up => LEFT_DESTINATION,
-                Direction::Left => BOTTOM_DESTINATION,
-                Direction::Right => UP_DESTINATION,
-            },
+        // Consider destination reached if within 50 pixels, once out of the junction
+        self.state == VehicleState::Exiting && self.path.length() - self.distance < 50.0
+    }
+
+    /// Distance left to its destination along the path
+    /// Every path ending in the same exit road lane ends on the same straight line, vehicles
+    /// from different approaches are ordered on the exit road by it
+    pub fn remaining(&self) -> f32 {
+        self.path.length() - self.distance
+    }
+
+    /// What the vehicle does at the intersection
+    pub fn movement(&self) -> Movement {
+        Movement::new(self.spawn, self.direction)
+    }
+
+    /// Whether the lane use of its lane allows its turn
+    pub fn is_in_right_lane(&self, config: &Config) -> bool {
+        config.lane_uses(self.spawn)[self.lane].allows(self.direction)
+    }
+
+    /// Lane left behind, while still partly in it during a lane change
+    pub fn previous_lane(&self) -> Option<usize> {
+        match self.lateral {
+            lateral if lateral < 0.0 => Some(self.lane - 1),
+            lateral if lateral > 0.0 => Some(self.lane + 1),
+            _ => None,
         }
     }
 
-    // Check if there's a vehicle ahead within safe distance
-    pub fn is_vehicle_ahead(&self, vehicles: &VecDeque<Vehicle>) -> Option<i32> {
-        for other in vehicles.iter() {
-            if other.id == self.id {
-                continue;
-            }
+    /// Whether part of the vehicle is in the given lane
+    pub fn is_in_lane(&self, lane: usize) -> bool {
+        self.lane == lane || self.previous_lane() == Some(lane)
+    }
 
-            // Check if the other vehicle is in the same lane and ahead
-            if self.is_vehicle_in_front(other) {
-                let distance = self.calculate_distance_to(other);
-                if distance < (SAFE_DISTANCE as f32 + VEHICLE_WIDTH as f32) {
-                    return Some(other.id);
-                }
-            }
+    // Move across into the next lane on the given side, the path is the one of the new lane
+    // from the same arc length
+    pub(crate) fn change_lane(&mut self, lane: usize, config: &Config, events: &mut EventLog) {
+        events.push(EventKind::LaneChanged {
+            id: self.id,
+            from: self.lane,
+            to: lane,
+        });
+        self.lateral += (self.lane as f32 - lane as f32) * config.lane_width as f32;
+        self.lane = lane;
+        self.wants_lane = None;
+        self.path = Arc::new(Path::new(self.movement(), lane, self.height, config));
+        self.place();
+    }
+
+    // Give up the turn wanted for another one, when stuck in a lane that does not allow it
+    pub(crate) fn reroute(&mut self, direction: Direction, config: &Config, events: &mut EventLog) {
+        let from = self.movement();
+        self.direction = direction;
+        self.wants_lane = None;
+        self.path = Arc::new(Path::new(self.movement(), self.lane, self.height, config));
+        self.place();
+        events.push(EventKind::VehicleRerouted {
+            id: self.id,
+            from,
+            to: self.movement(),
+        });
+    }
+
+    // Single place where the state changes, every transition is recorded as an event
+    pub(crate) fn set_state(&mut self, state: VehicleState, events: &mut EventLog) {
+        if self.state == state {
+            return;
         }
-        None
+        debug_assert!(
+            self.state.can_become(state),
+            "vehicle {} cannot go from {:?} to {:?}",
+            self.id,
+            self.state,
+            state
+        );
+        events.push(EventKind::VehicleStateChanged {
+            id: self.id,
+            from: self.state,
+            to: state,
+        });
+        self.state = state;
     }
 
-    // Check if another vehicle is in front of this one
-    fn is_vehicle_in_front(&self, other: &Vehicle) -> bool {
-        let tolerance = (VEHICLE_WIDTH as f32) / 2.0;
+    // Stop on the spot after a crash, the wreck is an obstacle for `clearance` seconds
+    pub(crate) fn crash(&mut self, clearance: f32, events: &mut EventLog) {
+        self.speed = 0.0;
+        self.stopping = false;
+        self.wants_lane = None;
+        self.clearance = clearance;
+        self.set_state(VehicleState::Crashed, events);
+    }
 
-        match self.spawn {
-            VehicleSpawn::North => {
-                // Moving south initially, check if other vehicle is ahead (further south) and in same lane
-                (other.position.0 - self.position.0).abs() < tolerance
-                    && other.position.1 > self.position.1
-            }
-            VehicleSpawn::South => {
-                // Moving north initially, check if other vehicle is ahead (further north) and in same lane
-                (other.position.0 - self.position.0).abs() < tolerance
-                    && other.position.1 < self.position.1
+    /// Emergency vehicle close enough to its stop line to preempt the lights, until it is out of
+    /// the junction or crashed
+    pub fn is_calling_preemption(&self, config: &Config) -> bool {
+        self.class.is_emergency()
+            && self.state != VehicleState::Crashed
+            && !self.has_left_junction()
+            && self.progress() >= -config.preemption_distance
+    }
+
+    /// Past its stop line and not yet out of the crosswalk it leaves the junction through
+    pub fn is_crossing_junction(&self) -> bool {
+        self.progress() > 0.0 && self.distance < self.path.crosswalk_exit()
+    }
+
+    /// Leaving the junction through the crosswalk of `leg`, and already inside the junction or
+    /// too close to the crosswalk to stop before it, until off it
+    pub fn is_committed_to_crosswalk(&self, leg: VehicleSpawn, config: &Config) -> bool {
+        self.movement().exit() == leg
+            && (self.progress() > 0.0
+                || self.distance + self.braking_distance(config) >= self.path.crosswalk_entry())
+            && self.distance < self.path.crosswalk_exit()
+    }
+
+    /// Closest vehicle ahead in the same approach lane, with the distance to it along the path
+    /// A vehicle turning another way is no longer ahead once its rear left the shared part and
+    /// it is out of the way of this vehicle, measured where this vehicle would touch it
+    pub fn is_vehicle_ahead<'a>(&self, vehicles: &'a [Vehicle]) -> Option<(&'a Vehicle, f32)> {
+        let mut closest: Option<(&Vehicle, f32)> = None;
+        for other in vehicles.iter() {
+            if other.id == self.id || other.distance <= self.distance {
+                continue;
             }
-            VehicleSpawn::East => {
-                // Moving west initially, check if other vehicle is ahead (further west) and in same lane
-                (other.position.1 - self.position.1).abs() < tolerance
-                    && other.position.0 < self.position.0
+            let rear = other.distance - other.height as f32 / 2.0;
+            let mut distance = other.distance - self.distance;
+            let shared = self.path.shared_length(&other.path);
+            if rear > shared {
+                // The paths drift apart past this distance
+                if other.distance > shared + 2.0 * self.reach(other) {
+                    continue;
+                }
+                let Some(touch) = self.distance_to_touch(other) else {
+                    continue;
+                };
+                distance = touch;
             }
-            VehicleSpawn::West => {
-                // Moving east initially, check if other vehicle is ahead (further east) and in same lane
-                (other.position.1 - self.position.1).abs() < tolerance
-                    && other.position.0 > self.position.0
+            if closest.is_none_or(|(_, d)| distance < d) {
+                closest = Some((other, distance));
             }
         }
+        closest
     }
 
-    // Calculate distance to another vehicle
-    fn calculate_distance_to(&self, other: &Vehicle) -> f32 {
-        ((self.position.0 - other.position.0).powi(2)
-            + (self.position.1 - other.position.1).powi(2))
-        .sqrt()
+    /// Closest vehicle ahead in the exit road lane the vehicle is on or heading to, whatever the
+    /// approach it came from, with the distance to it along the road
+    pub fn is_vehicle_ahead_on_exit<'a>(&self, exit: &'a [Vehicle]) -> Option<(&'a Vehicle, f32)> {
+        exit.iter()
+            .filter(|other| other.id != self.id && other.remaining() < self.remaining())
+            .map(|other| (other, self.remaining() - other.remaining()))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
     }
 
-    // Updated move method that returns true if vehicle should be removed
-    pub fn r#move(
+    /// Closest vehicle of another lane of the approach in the way of this vehicle ahead of it,
+    /// with the distance to it: moving across into its lane, waiting to be let in with room
+    /// left to stay behind it, or swinging into its path around the junction
+    /// During a lane change the vehicles ahead in the previous lane are still in the way
+    pub fn is_vehicle_cutting_in<'a>(&self, vehicles: &'a [Vehicle]) -> Option<(&'a Vehicle, f32)> {
+        vehicles
+            .iter()
+            .filter(|other| {
+                other.spawn == self.spawn && other.lane != self.lane && other.id != self.id
+            })
+            .filter_map(|other| {
+                let distance = other.distance - self.distance;
+                let gap = distance - (self.height + other.height) as f32 / 2.0;
+                let crossing = self
+                    .previous_lane()
+                    .is_some_and(|lane| other.is_in_lane(lane))
+                    || other.previous_lane() == Some(self.lane);
+                let waiting = other.wants_lane == Some(self.lane)
+                    && self.progress() <= 0.0
+                    && gap > self.driver.minimum_gap;
+                if distance > 0.0 && (crossing || waiting) {
+                    return Some((other, distance));
+                }
+                // Long vehicles sweep across the next lane in the turns, side by side the
+                // first one spawned goes first
+                let reach = self.reach(other);
+                let ahead = distance > 0.0 || (distance == 0.0 && other.id < self.id);
+                let near = self.progress() > -reach && other.progress() > -reach;
+                if ahead && near && distance <= 2.0 * reach {
+                    return self.distance_to_touch(other).map(|touch| (other, touch));
+                }
+                None
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    // Both vehicles fit within this distance of each other along their paths, whatever their
+    // headings
+    fn reach(&self, other: &Vehicle) -> f32 {
+        (self.height + other.height + self.width + other.width) as f32 / 2.0
+    }
+
+    // Distance to `other` as if it was ahead on the same path, from the first place along the
+    // path where this vehicle would touch it, walked pixel by pixel around it
+    fn distance_to_touch(&self, other: &Vehicle) -> Option<f32> {
+        let reach = self.reach(other);
+        let footprint = other.corners();
+        let start = self.distance.max(other.distance - reach);
+        let touch = (0..(2.0 * reach) as usize)
+            .map(|step| start + step as f32)
+            .find(|&s| overlap_depth(&self.corners_on_path(s), &footprint) > 0.0)?;
+        Some(touch - self.distance + (self.height + other.height) as f32 / 2.0)
+    }
+
+    // Move the vehicle by one simulation step of `dt` seconds, `vehicles` are the ones of the
+    // lane it is in, an approach lane or an exit road lane once Exiting
+    // The vehicle ends up Despawned once it reached its destination
+    pub(crate) fn r#move(
         &mut self,
-        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
-        lights: &HashMap<String, TrafficLight>,
-        vehicles: &VecDeque<Vehicle>, // Add vehicles parameter for collision detection
-        waiting: &mut HashMap<i32, Vehicle>,
-        junction: &mut HashSet<i32>,
-    ) -> bool {
+        step: &mut StepContext,
+        vehicles: &[Vehicle],
+        events: &mut EventLog,
+    ) {
         // Check if reached destination first
         if self.has_reached_destination() {
-            return true;
+            self.set_state(VehicleState::Despawned, events);
+            return;
+        }
+        // A wreck does not move, it is removed once cleared
+        if self.state == VehicleState::Crashed {
+            self.clearance -= step.dt;
+            if self.clearance <= 0.0 {
+                self.set_state(VehicleState::Despawned, events);
+            }
+            return;
         }
 
-        let mut should_stop = false;
+        let progress = self.progress();
+        // Before leaving the junction, the vehicles already on its exit road are ahead as well
+        let (same_lane, exit_road) = if self.state == VehicleState::Exiting {
+            (self.is_vehicle_ahead_on_exit(vehicles), None)
+        } else {
+            let exit = step.exits[self.movement().exit()][self.lane];
+            (
+                self.is_vehicle_ahead(vehicles),
+                self.is_vehicle_ahead_on_exit(exit),
+            )
+        };
+        let ahead = [
+            same_lane,
+            exit_road,
+            self.is_vehicle_cutting_in(step.vehicles),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        // Car following - the vehicle ahead, and the stop line when the light forbids crossing
+        // it, are both obstacles to stop behind
+        self.stopping = progress <= 0.0 && self.must_stop_at_line(step, progress);
+        let leader = ahead.map(|(other, distance)| Obstacle {
+            gap: distance - (self.height + other.height) as f32 / 2.0,
+            speed: other.speed,
+            minimum_gap: self.driver.minimum_gap,
+        });
+        let line = self.stopping.then_some(Obstacle {
+            gap: -progress + LINE_OVERSHOOT,
+            speed: 0.0,
+            minimum_gap: 0.0,
+        });
+        // Give way to the pedestrians on the crosswalk the vehicle leaves the junction through
+        let crosswalk = self.must_yield_to_pedestrians(step).then(|| Obstacle {
+            gap: self.path.crosswalk_entry() - self.distance,
+            speed: 0.0,
+            minimum_gap: 0.0,
+        });
+        let acceleration = [leader, line, crosswalk]
+            .into_iter()
+            .flatten()
+            .map(|obstacle| self.driver.acceleration(self.speed, Some(obstacle)))
+            .fold(self.driver.acceleration(self.speed, None), f32::min);
 
-        // Check traffic light
-        if let Some(light) = lights.get(self.spawn.as_str()) {
-            if self.is_at_light() && !light.state {
-                waiting.insert(self.id, self.to_owned());
-                should_stop = true;
+        let mut speed = (self.speed + acceleration * step.dt).max(0.0);
+        if speed < STOP_SPEED && acceleration < 0.0 {
+            speed = 0.0;
+        }
+        // A stopped driver moves off with a delay once the way ahead clears
+        if self.speed == 0.0 {
+            let clear = acceleration >= self.driver.max_acceleration * MOVE_OFF_ACCELERATION;
+            self.waiting = if clear { self.waiting + step.dt } else { 0.0 };
+            if !clear || self.waiting < self.driver.startup_delay {
+                speed = 0.0;
             }
         }
+        if speed > 0.0 {
+            self.waiting = 0.0;
+        }
+        let mut distance = (self.speed + speed) / 2.0 * step.dt;
 
-        // Check for vehicle ahead
-        if !should_stop && let Some(vehicle_id) = self.is_vehicle_ahead(vehicles) {
-            if waiting.contains_key(&vehicle_id) {
-                waiting.insert(self.id, self.to_owned());
+        // Never close the gap to the vehicle ahead below the minimum gap
+        if let Some(leader) = leader {
+            let free = (leader.gap - leader.minimum_gap).max(0.0);
+            if distance > free {
+                distance = free;
+                speed = speed.min(leader.speed);
             }
-            should_stop = true;
         }
 
-        if self.speed > 0 && !should_stop {
-            let offset: f32;
-            match self.direction {
-                Direction::Right => offset = CASE_SIZE as f32,
-                Direction::Left => offset = 2.0 * CASE_SIZE as f32,
-                Direction::Up => offset = 0.0,
-            };
-            if self.light_pasted() {
-                junction.insert(self.id);
-                waiting.remove(&self.id);
+        // Pull up on the line rather than crossing it without the right of way, unless the vehicle
+        // ahead already holds it back short of the line
+        if self.stopping && progress + distance >= 0.0 {
+            distance = -progress;
+            speed = 0.0;
+            self.set_state(VehicleState::StoppedAtLine, events);
+        }
+        self.speed = speed;
+
+        // Slowing down behind a queue makes this vehicle part of it
+        if let Some((ahead, _)) = ahead
+            && ahead.state.is_queued()
+            && self.state == VehicleState::Approaching
+            && speed < QUEUE_SPEED
+        {
+            self.set_state(VehicleState::Queued, events);
+        }
+
+        // Moving across to the new lane at a steady pace
+        if self.lateral != 0.0 {
+            let pace = step.config.lane_width as f32 / step.config.lane_change_time.max(step.dt);
+            let shift = (pace * step.dt).min(self.lateral.abs());
+            self.lateral -= shift * self.lateral.signum();
+            self.place();
+        }
+
+        if distance > 0.0 {
+            let turned = self.advance(distance);
+
+            // Entering the junction when crossing the stop line
+            if progress <= 0.0 && self.progress() > 0.0 {
+                self.set_state(VehicleState::InJunction, events);
+                step.junction_occupancy += 1;
+                step.entered.push(self.clone());
             }
-            if junction.contains(&self.id) {
-                let (x, y) = self.position;
-                if x < WEST_LIGHT.0 || x > EAST_LIGHT.0 || y < NORTH_LIGHT.1 || y > SOUTH_LIGHT.1 {
-                    junction.remove(&self.id);
-                }
+            if turned {
+                self.set_state(VehicleState::Turning, events);
             }
-            if self.is_at_turn(offset) && !self.as_turned {
-                self.as_turned = true;
-                self.update_vector();
+            if self.state.is_in_junction() && self.has_left_junction() {
+                self.set_state(VehicleState::Exiting, events);
+                step.junction_occupancy -= 1;
             }
-            let movement_distance = self.speed as f32 * 1.0 / 60.0;
-            self.position = (
-                self.position.0 + self.vector.0 * movement_distance,
-                self.position.1 + self.vector.1 * movement_distance,
-            );
-            self.update_shape();
         }
-
-        self.render(canvas);
-        false // Don't remove vehicle yet
     }
 
-    fn get_light_position(&self) -> (f32, f32) {
-        match self.spawn {
-            VehicleSpawn::East => EAST_LIGHT,
-            VehicleSpawn::South => SOUTH_LIGHT,
-            VehicleSpawn::North => NORTH_LIGHT,
-            VehicleSpawn::West => WEST_LIGHT,
+    // No conflicting vehicle of the opposing approach inside the junction, and the first one
+    // before the stop line not due there within `left_turn_gap` seconds with the right of way
+    fn has_gap(&self, step: &StepContext) -> bool {
+        let movement = self.movement();
+        let conflicting = |other: Movement| step.conflicts.conflicts(movement, other);
+        let opposite = self.spawn.opposite();
+        if step
+            .entered
+            .iter()
+            .any(|other| other.spawn == opposite && conflicting(other.movement()))
+        {
+            return false;
         }
+
+        // First vehicle before the stop line in every lane
+        let mut first: Vec<Option<(&Vehicle, f32)>> = vec![None; step.config.lanes as usize];
+        for other in step.vehicles.iter().filter(|other| other.spawn == opposite) {
+            if other.state.is_in_junction() && conflicting(other.movement()) {
+                return false;
+            }
+            let progress = other.progress();
+            let first = &mut first[other.lane];
+            if progress <= 0.0 && first.is_none_or(|(_, p)| progress > p) {
+                *first = Some((other, progress));
+            }
+        }
+
+        // The vehicles behind the first one of a lane cannot reach the junction before it
+        first.into_iter().flatten().all(|(other, progress)| {
+            if !conflicting(other.movement()) || !other.is_in_right_lane(step.config) {
+                return true;
+            }
+            // Opposing permissive left turns wait for us too, they only block once inside
+            let head = step.lights[other.spawn].head(other.direction);
+            let has_right_of_way = !head.is_permissive()
+                && matches!(
+                    head.state(),
+                    SignalState::RedAmber | SignalState::Green | SignalState::Amber
+                );
+            let time_to_line = other.driver.time_to_cover(other.speed, -progress);
+            !has_right_of_way || time_to_line >= step.config.left_turn_gap
+        })
     }
 
-    fn is_at_light(&self) -> bool {
-        let x = (self.position.0 - self.get_light_position().0).abs();
-        let y = (self.position.1 - self.get_light_position().1).abs();
-        x != 0.0 && x < 2.5 || y != 0.0 && y < 2.5
+    // Center out of the junction bounds, measured along the path
+    fn has_left_junction(&self) -> bool {
+        self.distance >= self.path.junction_exit()
     }
 
-    fn light_pasted(&self) -> bool {
-        let offset = CASE_SIZE as f32;
-        let x = self.position.0 - self.get_light_position().0;
-        let y = self.position.1 - self.get_light_position().1;
-        match self.spawn {
-            VehicleSpawn::West => (x - offset).abs() <= 5.0,
-            VehicleSpawn::North => (y - offset).abs() <= 5.0,
-            VehicleSpawn::South => y + offset <= 5.0,
-            VehicleSpawn::East => x + offset <= 5.0,
-        }
+    // An emergency vehicle of another approach is on its way through the junction, on a path
+    // crossing this one
+    fn must_yield_to_emergency(&self, step: &StepContext) -> bool {
+        let movement = self.movement();
+        !self.class.is_emergency()
+            && step.vehicles.iter().any(|other| {
+                other.spawn != self.spawn
+                    && other.is_calling_preemption(step.config)
+                    && step.conflicts.conflicts(movement, other.movement())
+            })
     }
 
-    fn is_at_turn(&self, offset: f32) -> bool {
-        if offset == 0.0 {
-            return true;
-        };
-        let x = self.position.0 - self.get_light_position().0;
-        let y = self.position.1 - self.get_light_position().1;
-        match self.spawn {
-            VehicleSpawn::West => (x - offset).abs() <= 1.7,
-            VehicleSpawn::North => (y - offset).abs() <= 1.7,
-            VehicleSpawn::South => y + offset <= 1.7,
-            VehicleSpawn::East => x + offset <= 1.7,
-        }
+    // Pedestrians on the crosswalk the vehicle leaves the junction through, or about to step in
+    // on walk
+    fn has_pedestrians_on_exit(&self, step: &StepContext) -> bool {
+        let leg = self.movement().exit();
+        let walk = step.crosswalks[leg].is_walk();
+        step.pedestrians.iter().any(|pedestrian| {
+            pedestrian.crosswalk == leg
+                && (pedestrian.is_crossing()
+                    || (walk && pedestrian.state == PedestrianState::Waiting))
+        })
     }
 
-    fn update_vector(&mut self) {
-        match self.direction {
-            Direction::Left => {
-                self.vector = (self.vector.1, -self.vector.0);
+    // Inside the junction, pedestrians already on the exit crosswalk and still room to stop
+    // before it
+    // The pedestrians waiting at the curb let the vehicles inside the junction go first, the
+    // others wait at their stop line not to block the junction
+    fn must_yield_to_pedestrians(&self, step: &StepContext) -> bool {
+        let leg = self.movement().exit();
+        let gap = self.path.crosswalk_entry() - self.distance;
+        self.progress() > 0.0
+            && gap > 0.0
+            && (self.speed == 0.0 || gap >= self.braking_distance(step.config))
+            && step
+                .pedestrians
+                .iter()
+                .any(|pedestrian| pedestrian.crosswalk == leg && pedestrian.is_crossing())
+    }
+
+    // A vehicle of another lane of the approach is in the way of this one crossing the junction,
+    // on a path touching this one: crossing it, started crossing it during this step, or about
+    // to cross it alongside and not held at its line. A vehicle waiting before its line where
+    // this one would touch it is in the way as well
+    // Of two vehicles in the way of each other, the first one spawned goes first, unless the
+    // other one can no longer stop or is itself in the way
+    fn has_vehicle_swinging_in(&self, step: &StepContext) -> bool {
+        let conflicts = step.conflicts;
+        let neighbours = step
+            .vehicles
+            .iter()
+            .chain(&step.entered)
+            .filter(|other| other.spawn == self.spawn && other.lane != self.lane);
+        neighbours.into_iter().any(|other| {
+            if other.state.is_in_junction() {
+                return conflicts.swings_into(self, other);
             }
-            Direction::Right => {
-                self.vector = (-self.vector.1, self.vector.0);
+            if other.progress() <= -self.reach(other) {
+                return false;
             }
-            Direction::Up => {
-                self.vector = self.vector;
+            let blocked = conflicts.sweep_touches(other, &self.corners());
+            if conflicts.sweep_touches(self, &other.corners()) {
+                return other.id < self.id || !blocked;
             }
+            let committed = -other.progress() < other.braking_distance(step.config);
+            conflicts.swings_into(self, other)
+                && !other.stopping
+                && !blocked
+                && (other.id < self.id || committed)
+        })
+    }
+
+    // Distance needed to stop from the current speed, braking hard
+    pub(crate) fn braking_distance(&self, config: &Config) -> f32 {
+        self.speed * self.speed / (2.0 * config.braking_deceleration)
+    }
+
+    // Whether the line is still beyond the braking distance, a driver who started braking for
+    // it keeps stopping
+    fn can_stop_before_line(&self, step: &StepContext, progress: f32) -> bool {
+        self.stopping || -progress >= self.braking_distance(step.config)
+    }
+
+    // Whether the light of the approach forbids crossing the stop line, `progress` away from it
+    fn must_stop_at_line(&self, step: &StepContext, progress: f32) -> bool {
+        let yielding = self.must_yield_to_emergency(step)
+            || self.has_pedestrians_on_exit(step)
+            || self.has_vehicle_swinging_in(step);
+        if yielding && self.can_stop_before_line(step, progress) {
+            return true;
+        }
+        // Not in a lane it may turn from, or not yet all the way into it
+        if !self.is_in_right_lane(step.config) || self.lateral != 0.0 {
+            return true;
+        }
+        let light = step.lights[self.spawn].head(self.direction);
+        // Permissive green - left turn waiting for a gap in the opposing traffic
+        if light.is_permissive() && !self.has_gap(step) {
+            return true;
+        }
+        match light.state() {
+            SignalState::Green => false,
+            SignalState::Red | SignalState::RedAmber => true,
+            // Stop if the line is still beyond the braking distance, until the amber is over
+            SignalState::Amber => self.can_stop_before_line(step, progress),
+            // Light out of order - give way to whoever is already inside the junction
+            SignalState::FlashingAmber | SignalState::Off => step.junction_occupancy > 0,
         }
     }
 
-    fn update_shape(&mut self) {
-        self.shape = Rect::new(
-            self.position.0 as i32 - (VEHICLE_WIDTH / 2) as i32,
-            self.position.1 as i32 - (VEHICLE_HEIGHT / 2) as i32,
-            VEHICLE_WIDTH,
-            VEHICLE_HEIGHT,
-        )
+    /// Signed distance travelled past the stop line, along the path
+    pub fn progress(&self) -> f32 {
+        self.distance - self.path.stop_line()
+    }
+
+    // Place the vehicle exactly at the given progress on its path
+    fn snap_to_progress(&mut self, progress: f32) {
+        self.distance = self.path.stop_line() + progress;
+        self.place();
+    }
+
+    // Position and heading at the arc length travelled, beside the path during a lane change
+    fn place(&mut self) {
+        let ((x, y), (hx, hy)) = self.path.at(self.distance);
+        self.position = (x - hy * self.lateral, y + hx * self.lateral);
+        self.vector = (hx, hy);
     }
 
-    pub fn accelerate(&mut self) {
-        self.speed = VEHICLE_SPEED;
+    // Move along the path, the heading follows the curve of a turn
+    // Returns true if the vehicle started turning during this step
+    fn advance(&mut self, distance: f32) -> bool {
+        let before = self.distance;
+        self.distance += distance;
+        self.place();
+        self.path
+            .turn_start()
+            .is_some_and(|start| before < start && self.distance >= start)
     }
 }
 
-// Function to check if a new vehicle can be spawned
-pub fn can_spawn_vehicle(lane: &VecDeque<Vehicle>) -> bool {
-    if lane.is_empty() {
-        return true;
+// Footprints of a vehicle of the given class doing `movement` from the given lane, every
+// `step` pixels from the stop line until it is out of the junction
+pub(crate) fn sweep_junction(
+    movement: Movement,
+    lane: usize,
+    class: VehicleClass,
+    config: &Config,
+    step: f32,
+) -> Vec<Footprint> {
+    let mut vehicle = Vehicle::new(0, class, movement.turn, movement.from, lane, config);
+    vehicle.snap_to_progress(0.0);
+
+    let mut footprints = vec![vehicle.corners()];
+    while !vehicle.has_left_junction() {
+        vehicle.advance(step);
+        footprints.push(vehicle.corners());
     }
+    footprints
+}
 
-    // Get the last spawned vehicle (most recent)
-    if let Some(last_vehicle) = lane.back() {
-        // Calculate distance from spawn point to last vehicle
-        let spawn_distance = match last_vehicle.spawn {
-            VehicleSpawn::North => {
-                // For north spawn, check if last vehicle has moved far enough down
-                let spawn_pos = crate::config::UP_SPAWN;
-                (last_vehicle.position.1 - spawn_pos.1).abs()
-            }
-            VehicleSpawn::South => {
-                // For south spawn, check if last vehicle has moved far enough up
-                let spawn_pos = crate::config::BOTTOM_SPAWN;
-                (spawn_pos.1 - last_vehicle.position.1).abs()
-            }
-            VehicleSpawn::East => {
-                // For east spawn, check if last vehicle has moved far enough left
-                let spawn_pos = crate::config::RIGHT_SPAWN;
-                (spawn_pos.0 - last_vehicle.position.0).abs()
-            }
-            VehicleSpawn::West => {
-                // For west spawn, check if last vehicle has moved far enough right
-                let spawn_pos = crate::config::LEFT_SPAWN;
-                (last_vehicle.position.0 - spawn_pos.0).abs()
+/// Depth of the overlap of two convex footprints, zero or less when they are apart
+/// Separating axis test on the edges of both footprints
+pub fn overlap_depth(a: &Footprint, b: &Footprint) -> f32 {
+    let mut depth = f32::INFINITY;
+    for corners in [a, b] {
+        for i in 0..2 {
+            let edge = (
+                corners[i + 1].0 - corners[i].0,
+                corners[i + 1].1 - corners[i].1,
+            );
+            let length = (edge.0 * edge.0 + edge.1 * edge.1).sqrt();
+            if length == 0.0 {
+                continue;
             }
-        };
-
-        // Ensure there's enough distance (safe distance + vehicle width)
-        let min_distance = (SAFE_DISTANCE + VEHICLE_WIDTH) as f32;
-        spawn_distance >= min_distance
-    } else {
-        true
+            let axis = (edge.0 / length, edge.1 / length);
+            let project = |footprint: &Footprint| {
+                footprint
+                    .iter()
+                    .map(|&(x, y)| x * axis.0 + y * axis.1)
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+                        (min.min(p), max.max(p))
+                    })
+            };
+            let ((min_a, max_a), (min_b, max_b)) = (project(a), project(b));
+            depth = depth.min(max_a.min(max_b) - min_a.max(min_b));
+        }
     }
+    depth
+}
+
+// Function to check if a new vehicle can be spawned behind the last vehicle of its lane
+pub(crate) fn can_spawn_vehicle(
+    last_vehicle: Option<&Vehicle>,
+    class: VehicleClass,
+    config: &Config,
+) -> bool {
+    let Some(last_vehicle) = last_vehicle else {
+        return true;
+    };
+
+    // Check if the last vehicle has moved far enough along its path
+    // Ensure there's enough distance (safe distance + half of both vehicle lengths) once the
+    // new vehicle, entering at its desired speed, braked comfortably down to the speed of the
+    // last one
+    let spec = config.classes.get(class);
+    let driver = Driver::new(spec, config);
+    let braking = (driver.desired_speed.powi(2) - last_vehicle.speed.powi(2)).max(0.0)
+        / (2.0 * driver.comfortable_deceleration);
+    let lengths = (last_vehicle.height + spec.length) as f32 / 2.0;
+    let min_distance = config.safe_distance as f32 + lengths + braking;
+    last_vehicle.distance >= min_distance
 }